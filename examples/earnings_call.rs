@@ -107,6 +107,7 @@ async fn main() -> Result<()> {
             Role::Assistant,
             vec![ContentType::Text {
                 text: "You are an expert financial analyst.".to_string(),
+                cache_control: None,
             }],
         )
         .add_message(
@@ -116,13 +117,12 @@ async fn main() -> Result<()> {
                     "Analyze the this quarterly earnings call:\n\n{}",
                     earnings_call_transcript
                 ),
+                cache_control: None,
             }],
         )
         .max_tokens(200)
         .tools(vec![tool])
         .tool_choice(ToolChoice::Specific {
-            // TODO: should name be checked that it matches
-            // the tool?
             name: "analyze_earnings_call".to_string(),
             disable_parallel_tool_use: Some(false),
         })
@@ -130,6 +130,7 @@ async fn main() -> Result<()> {
         .unwrap();
 
     let response = chat.call().await.unwrap();
+    let response = serde_json::to_string(&response).unwrap();
     let response = pretty_print(&response).unwrap();
 
     println!("{}", response);
@@ -1,8 +1,7 @@
 use anyhow::Result;
-use jsonxf::pretty_print;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
-use tyrell::{ClaudeRequest, ContentType, Model, Role, Tool, ToolBuilder, ToolChoice};
+use tyrell::{Client, Model, ToolBuilder};
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct SuperBowl {
@@ -26,38 +25,17 @@ impl ToolBuilder for SuperBowl {
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    let tool = Tool::new::<SuperBowl>();
+    let client = Client::new();
 
-    let chat = ClaudeRequest::builder()
-        .model(Model::Sonnet35)
-        .add_message(
-            Role::Assistant,
-            vec![ContentType::Text {
-                text: "You are an NFL historian. Extract the information from the text".to_string(),
-            }],
+    let super_bowl: SuperBowl = client
+        .analyze(
+            Model::Sonnet35,
+            "You are an NFL historian. Extract the information from the text",
+            "The Green Bay Packers beat the Miami Dolphins in the 1982 Super Bowl 31-10.",
         )
-        .add_message(
-            Role::User,
-            vec![ContentType::Text {
-                text: "The Green Bay Packers beat the Miami Dolphins in the 1982 Super Bowl 31-10."
-                    .to_string(),
-            }],
-        )
-        .max_tokens(200)
-        .tools(vec![tool])
-        .tool_choice(ToolChoice::Specific {
-            // TODO: should name be checked that it matches
-            // the tool?
-            name: "extract_super_bowl_info".to_string(),
-            disable_parallel_tool_use: Some(false),
-        })
-        .build()
-        .unwrap();
-
-    let response = chat.call().await.unwrap();
-    let response = pretty_print(&response).unwrap();
+        .await?;
 
-    println!("{}", response);
+    println!("{}", serde_json::to_string_pretty(&super_bowl)?);
 
     Ok(())
 }
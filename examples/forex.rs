@@ -50,12 +50,14 @@ async fn analyze_news(news: &str, country: &str) -> String {
             Role::Assistant,
             vec![ContentType::Text {
                 text: "You are an expert economic analyst specializing in forex markets.".to_string(),
+                cache_control: None,
             }],
         )
         .add_message(
             Role::User,
             vec![ContentType::Text {
                 text: format!("Analyze this news article about the {} economy and extract key information:\n\n{}", country, news),
+                cache_control: None,
             }],
         )
         .max_tokens(512)
@@ -66,7 +68,8 @@ async fn analyze_news(news: &str, country: &str) -> String {
         })
         .build().expect("failed to build request");
 
-    chat.call().await.expect("failed to call Claude")
+    let response = chat.call().await.expect("failed to call Claude");
+    serde_json::to_string(&response).expect("failed to serialize response")
  
 }
 
@@ -107,12 +110,14 @@ async fn recommend_forex_trades(analyses: String) -> String {
             Role::Assistant,
             vec![ContentType::Text {
                 text: "You are an expert forex trader with deep knowledge of global economics.".to_string(),
+                cache_control: None,
             }],
         )
         .add_message(
             Role::User,
             vec![ContentType::Text {
                 text: format!("Based on these economic analyses, recommend forex currency pairs to trade:\n\n{}", analyses_json),
+                cache_control: None,
             }],
         )
         .max_tokens(512)
@@ -123,7 +128,8 @@ async fn recommend_forex_trades(analyses: String) -> String {
         })
         .build().expect("failed to call claude");
 
-    chat.call().await.expect("failed to call claude")
+    let response = chat.call().await.expect("failed to call claude");
+    serde_json::to_string(&response).expect("failed to serialize response")
 }
 
 
@@ -133,18 +133,21 @@ async fn main() -> Result<()> {
             Role::Assistant,
             vec![ContentType::Text {
                 text: "You are a lead software engineer helping prioritize a backlog. You are given the current code base an our open github issues. Use this context to prioritize tasks and suggest implementations. You must give code suggestions and write tests.".to_string(),
+                cache_control: None,
             }],
         )
         .add_message(
             Role::User,
             vec![ContentType::Text {
                 text: format!("code base: {}", source_code).to_string(),
+                cache_control: None,
             }],
         )
         .add_message(
             Role::User,
             vec![ContentType::Text {
                 text: format!("open issues: {}", extracted_issues).to_string(),
+                cache_control: None,
             }],
         )
         .max_tokens(2048)
@@ -157,6 +160,7 @@ async fn main() -> Result<()> {
         .unwrap();
 
     let response = chat.call().await.unwrap();
+    let response = serde_json::to_string(&response).unwrap();
     let response = pretty_print(&response).unwrap();
 
     println!("{}", response);
@@ -104,6 +104,7 @@ async fn main() -> Result<()> {
             Role::Assistant,
             vec![ContentType::Text {
                 text: "You are an expert financial analyst specializing in tech industry sentiment analysis.".to_string(),
+                cache_control: None,
 
             }],
         )
@@ -111,13 +112,12 @@ async fn main() -> Result<()> {
             Role::User,
             vec![ContentType::Text {
                 text: format!("Analyze the sentiment of this news article:\n\n{}", news_article),
+                cache_control: None,
             }],
         )
         .max_tokens(200)
         .tools(vec![tool])
         .tool_choice(ToolChoice::Specific {
-            // TODO: should name be checked that it matches
-            // the tool?
             name: "analyze_sentiment".to_string(),
             disable_parallel_tool_use: Some(false),
         })
@@ -125,6 +125,7 @@ async fn main() -> Result<()> {
         .unwrap();
 
     let response = chat.call().await.unwrap();
+    let response = serde_json::to_string(&response).unwrap();
     let response = pretty_print(&response).unwrap();
 
     println!("{}", response);
@@ -9,6 +9,7 @@ async fn main() {
             Role::User,
             vec![ContentType::Text {
                 text: "who was the 16th president of the USA?".to_string(),
+                cache_control: None,
             }],
         )
         .max_tokens(200)
@@ -16,6 +17,7 @@ async fn main() {
         .unwrap();
 
     let response = chat.call().await.unwrap();
+    let response = serde_json::to_string(&response).unwrap();
     let response = pretty_print(&response).unwrap();
 
     println!("{}", response);
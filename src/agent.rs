@@ -0,0 +1,523 @@
+//! Running tool-use rounds automatically.
+
+use crate::helpers::DEFAULT_MAX_TOKENS;
+use crate::{
+    ClaudeRequest, ClaudeResponse, Client, ContentType, Error, Message, Model, Result, Role,
+    StopReason, Tool, ToolBuilder, ToolResult, ToolUse,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Error returned by a [`ToolHandler`].
+pub type ToolHandlerError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Runs a tool with the input chosen by the model and returns the tool's output.
+///
+/// Handlers run on Tokio's blocking thread pool, so they may block, e.g. on file or network I/O.
+pub type ToolHandler = Arc<dyn Fn(Value) -> Result<String, ToolHandlerError> + Send + Sync>;
+
+/// Represents the outcome of [`Client::run_tools`].
+#[derive(Debug, Clone)]
+pub struct ToolRun {
+    /// The final response, which did not ask for another tool.
+    pub response: ClaudeResponse,
+    /// The full conversation, including the final assistant turn.
+    pub messages: Vec<Message>,
+}
+
+impl Client {
+    /// Calls the API and runs the requested tools until the model stops asking for them.
+    ///
+    /// Each tool-use block in a response is dispatched to the handler registered under the
+    /// tool's name. When the model asks for several tools at once, the handlers run
+    /// concurrently on the blocking thread pool and all outputs are sent back together as
+    /// tool results in a single new user turn. A handler that fails, or a tool without a
+    /// handler, is reported to the model as an error result so it can recover.
+    /// Errors with [`Error::ToolLoopLimit`] if the model still wants a tool after
+    /// `max_iterations` calls.
+    pub async fn run_tools(
+        &self,
+        request: &ClaudeRequest,
+        handlers: &HashMap<String, ToolHandler>,
+        max_iterations: usize,
+    ) -> Result<ToolRun> {
+        let mut request = request.clone();
+
+        for _ in 0..max_iterations {
+            let response = self.call(&request).await?;
+
+            request.messages.push(response.assistant_message());
+
+            if response.stop_reason != Some(StopReason::ToolUse) {
+                return Ok(ToolRun {
+                    response,
+                    messages: request.messages,
+                });
+            }
+
+            let tool_uses = response.unique_tool_uses()?;
+
+            let tasks = tool_uses.iter().map(|tool_use| {
+                let handler = handlers.get(&tool_use.name).cloned();
+                let tool_use = (*tool_use).clone();
+                tokio::task::spawn_blocking(move || run_handler(handler, tool_use))
+            });
+            let results = futures::future::join_all(tasks)
+                .await
+                .into_iter()
+                .map(|task| {
+                    let result =
+                        task.unwrap_or_else(|error| std::panic::resume_unwind(error.into_panic()));
+                    ContentType::ToolResult(result)
+                })
+                .collect();
+
+            request.messages.push(Message {
+                role: Role::User,
+                content: results,
+            });
+        }
+
+        Err(Error::ToolLoopLimit(max_iterations))
+    }
+}
+
+/// Runs `handler` on the input of `tool_use`, turning a failure into an error result.
+fn run_handler(handler: Option<ToolHandler>, tool_use: ToolUse) -> ToolResult {
+    let output = match handler {
+        Some(handler) => handler(tool_use.input).map_err(|e| e.to_string()),
+        None => Err(format!(
+            "No handler registered for tool `{}`",
+            tool_use.name
+        )),
+    };
+
+    match output {
+        Ok(output) => ToolResult::new(tool_use.id, output),
+        Err(message) => ToolResult::error(tool_use.id, message),
+    }
+}
+
+/// Tool calls allowed per [`Agent::run`] unless set with [`Agent::max_iterations`].
+const DEFAULT_MAX_ITERATIONS: usize = 10;
+
+/// Bundles a client, model, system prompt and tools with their handlers, so a tool-using
+/// conversation can be declared once and run repeatedly.
+pub struct Agent {
+    client: Client,
+    model: Model,
+    system: Option<String>,
+    max_tokens: u32,
+    max_iterations: usize,
+    tools: Vec<Tool>,
+    handlers: HashMap<String, ToolHandler>,
+    keep_history: bool,
+    history: Vec<Message>,
+}
+
+impl Agent {
+    /// Creates a new Agent without tools that forgets each conversation after it is run.
+    pub fn new(client: Client, model: Model) -> Self {
+        Agent {
+            client,
+            model,
+            system: None,
+            max_tokens: DEFAULT_MAX_TOKENS,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            tools: Vec::new(),
+            handlers: HashMap::new(),
+            keep_history: false,
+            history: Vec::new(),
+        }
+    }
+
+    /// Sets the system prompt sent with every request.
+    pub fn system(mut self, system: impl Into<String>) -> Self {
+        self.system = Some(system.into());
+        self
+    }
+
+    /// Sets the maximum number of tokens to generate per response.
+    pub fn max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    /// Sets how many calls a single [`run`](Self::run) may make before giving up.
+    pub fn max_iterations(mut self, max_iterations: usize) -> Self {
+        self.max_iterations = max_iterations;
+        self
+    }
+
+    /// Registers the tool generated from `T`, run by `handler` when the model calls it.
+    pub fn tool<T, F>(mut self, handler: F) -> Self
+    where
+        T: ToolBuilder,
+        F: Fn(Value) -> Result<String, ToolHandlerError> + Send + Sync + 'static,
+    {
+        self.tools.push(Tool::new::<T>());
+        self.handlers
+            .insert(T::name().to_string(), Arc::new(handler));
+        self
+    }
+
+    /// Keeps the conversation across [`run`](Self::run) calls, so each message continues it.
+    pub fn keep_history(mut self, keep_history: bool) -> Self {
+        self.keep_history = keep_history;
+        self
+    }
+
+    /// Returns the conversation so far, which is empty unless history is kept.
+    pub fn history(&self) -> &[Message] {
+        &self.history
+    }
+
+    /// Forgets the conversation so far.
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    /// Sends `message` and runs any requested tools until the model gives a final response.
+    pub async fn run(&mut self, message: impl Into<String>) -> Result<ClaudeResponse> {
+        let mut builder = ClaudeRequest::builder()
+            .model(self.model.clone())
+            .max_tokens(self.max_tokens)
+            .messages(self.history.clone())
+            .add_message(Role::User, vec![ContentType::text(message)]);
+        if let Some(system) = &self.system {
+            builder = builder.system(system.as_str());
+        }
+        if !self.tools.is_empty() {
+            builder = builder.tools(self.tools.clone());
+        }
+
+        let run = self
+            .client
+            .run_tools(&builder.build()?, &self.handlers, self.max_iterations)
+            .await?;
+
+        if self.keep_history {
+            self.history = run.messages;
+        }
+
+        Ok(run.response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::message_json;
+    use crate::{Model, Tool, ToolBuilder};
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+    use wiremock::matchers::{body_partial_json, body_string_contains, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    struct GetWeather {
+        location: String,
+    }
+
+    impl ToolBuilder for GetWeather {
+        fn name() -> &'static str {
+            "get_weather"
+        }
+
+        fn description() -> Option<&'static str> {
+            Some("Get the current weather in a given location")
+        }
+    }
+
+    fn response(content: Value, stop_reason: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(message_json(content, stop_reason))
+    }
+
+    #[tokio::test]
+    async fn test_run_tools_two_rounds() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("tool_result"))
+            .respond_with(response(
+                json!([{"type": "text", "text": "It is 15 degrees in San Francisco."}]),
+                "end_turn",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(response(
+                json!([{
+                    "type": "tool_use",
+                    "id": "toolu_01",
+                    "name": "get_weather",
+                    "input": {"location": "San Francisco, CA"}
+                }]),
+                "tool_use",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut handlers: HashMap<String, ToolHandler> = HashMap::new();
+        handlers.insert(
+            "get_weather".to_string(),
+            Arc::new(|input| {
+                let input: GetWeather = serde_json::from_value(input)?;
+                Ok(format!("15 degrees in {}", input.location))
+            }),
+        );
+
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "What's the weather in San Francisco?".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(100)
+            .tools(vec![Tool::new::<GetWeather>()])
+            .build()
+            .expect("Failed to build request");
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let run = client.run_tools(&request, &handlers, 5).await?;
+
+        assert_eq!(run.response.stop_reason, Some(StopReason::EndTurn));
+        assert_eq!(run.messages.len(), 4);
+        assert!(matches!(
+            &run.messages[2].content[0],
+            ContentType::ToolResult(result)
+                if result.tool_use_id == "toolu_01"
+                    && result.content.as_text() == Some("15 degrees in San Francisco, CA")
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_tools_reports_handler_errors() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains(r#""is_error":true"#))
+            .respond_with(response(
+                json!([{"type": "text", "text": "I couldn't find that city."}]),
+                "end_turn",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(response(
+                json!([{
+                    "type": "tool_use",
+                    "id": "toolu_01",
+                    "name": "get_weather",
+                    "input": {"location": "Atlantis"}
+                }]),
+                "tool_use",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut handlers: HashMap<String, ToolHandler> = HashMap::new();
+        handlers.insert(
+            "get_weather".to_string(),
+            Arc::new(|input| {
+                let input: GetWeather = serde_json::from_value(input)?;
+                Err(format!("Unknown location: {}", input.location).into())
+            }),
+        );
+
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .user("What's the weather in Atlantis?")
+            .max_tokens(100)
+            .tools(vec![Tool::new::<GetWeather>()])
+            .build()
+            .expect("Failed to build request");
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let run = client.run_tools(&request, &handlers, 5).await?;
+
+        assert_eq!(run.response.text(), "I couldn't find that city.");
+        assert!(matches!(
+            &run.messages[2].content[0],
+            ContentType::ToolResult(result)
+                if result.is_error && result.content.as_text() == Some("Unknown location: Atlantis")
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_run_tools_parallel_calls() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("tool_result"))
+            .respond_with(response(
+                json!([{"type": "text", "text": "Both cities are mild today."}]),
+                "end_turn",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(response(
+                json!([
+                    {
+                        "type": "tool_use",
+                        "id": "toolu_01",
+                        "name": "get_weather",
+                        "input": {"location": "San Francisco, CA"}
+                    },
+                    {
+                        "type": "tool_use",
+                        "id": "toolu_02",
+                        "name": "get_weather",
+                        "input": {"location": "Portland, OR"}
+                    }
+                ]),
+                "tool_use",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // Each handler takes a while, so both are running at once unless they run in turn.
+        let running = Arc::new(AtomicUsize::new(0));
+        let most_running = Arc::new(AtomicUsize::new(0));
+        let mut handlers: HashMap<String, ToolHandler> = HashMap::new();
+        handlers.insert("get_weather".to_string(), {
+            let (running, most_running) = (running.clone(), most_running.clone());
+            Arc::new(move |input| {
+                let input: GetWeather = serde_json::from_value(input)?;
+                let now_running = running.fetch_add(1, Ordering::SeqCst) + 1;
+                most_running.fetch_max(now_running, Ordering::SeqCst);
+                std::thread::sleep(Duration::from_millis(100));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok(format!("15 degrees in {}", input.location))
+            })
+        });
+
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "What's the weather in San Francisco and Portland?".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(100)
+            .tools(vec![Tool::new::<GetWeather>()])
+            .build()
+            .expect("Failed to build request");
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let run = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.run_tools(&request, &handlers, 5),
+        )
+        .await
+        .expect("run_tools timed out")?;
+
+        assert_eq!(most_running.load(Ordering::SeqCst), 2);
+        assert_eq!(run.messages.len(), 4);
+        let results: Vec<_> = run.messages[2]
+            .content
+            .iter()
+            .map(|content| match content {
+                ContentType::ToolResult(result) => {
+                    (result.tool_use_id.as_str(), result.content.as_text())
+                }
+                other => panic!("Expected a tool result, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                ("toolu_01", Some("15 degrees in San Francisco, CA")),
+                ("toolu_02", Some("15 degrees in Portland, OR")),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_agent_runs_tools_and_keeps_history() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({
+                "messages": [
+                    {"role": "user", "content": [{"text": "What's the weather in San Francisco?"}]},
+                    {"role": "assistant", "content": [{"type": "tool_use", "id": "toolu_01"}]},
+                    {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "toolu_01"}]},
+                    {"role": "assistant", "content": [{"text": "It is 15 degrees in San Francisco."}]},
+                    {"role": "user", "content": [{"text": "Is that warm?"}]}
+                ]
+            })))
+            .respond_with(response(
+                json!([{"type": "text", "text": "It is mild."}]),
+                "end_turn",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("tool_result"))
+            .respond_with(response(
+                json!([{"type": "text", "text": "It is 15 degrees in San Francisco."}]),
+                "end_turn",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("You report the weather."))
+            .respond_with(response(
+                json!([{
+                    "type": "tool_use",
+                    "id": "toolu_01",
+                    "name": "get_weather",
+                    "input": {"location": "San Francisco, CA"}
+                }]),
+                "tool_use",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let mut agent = Agent::new(client, Model::Haiku3)
+            .system("You report the weather.")
+            .tool::<GetWeather, _>(|input| {
+                let input: GetWeather = serde_json::from_value(input)?;
+                Ok(format!("15 degrees in {}", input.location))
+            })
+            .keep_history(true);
+
+        let response = agent.run("What's the weather in San Francisco?").await?;
+
+        assert_eq!(response.stop_reason, Some(StopReason::EndTurn));
+        assert_eq!(agent.history().len(), 4);
+        assert_eq!(agent.history()[3].role, Role::Assistant);
+
+        let response = agent.run("Is that warm?").await?;
+        assert_eq!(response.text(), "It is mild.");
+        assert_eq!(agent.history().len(), 6);
+
+        agent.clear_history();
+        assert!(agent.history().is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+//! Sending many requests at once through the Message Batches API.
+
+use crate::error::ApiErrorBody;
+use crate::{ClaudeRequest, ClaudeResponse, Client, Error, Result};
+use futures::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+/// Represents the processing status of a [`MessageBatch`].
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    InProgress,
+    Canceling,
+    Ended,
+}
+
+/// Represents how many requests in a batch are in each state.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct BatchRequestCounts {
+    pub processing: u32,
+    pub succeeded: u32,
+    pub errored: u32,
+    pub canceled: u32,
+    pub expired: u32,
+}
+
+/// Represents a batch of message requests processed asynchronously.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MessageBatch {
+    pub id: String,
+    pub processing_status: BatchStatus,
+    pub request_counts: BatchRequestCounts,
+    pub created_at: String,
+    pub expires_at: String,
+    pub ended_at: Option<String>,
+    pub cancel_initiated_at: Option<String>,
+    pub results_url: Option<String>,
+}
+
+/// Represents the result of one request in a batch.
+#[derive(Debug, Clone)]
+pub struct BatchResult {
+    /// The identifier given to the request in [`Client::create_batch`].
+    pub custom_id: String,
+    pub outcome: BatchOutcome,
+}
+
+/// Represents how a request in a batch finished.
+#[derive(Debug, Clone)]
+pub enum BatchOutcome {
+    Succeeded(ClaudeResponse),
+    Errored { error_type: String, message: String },
+    Canceled,
+    Expired,
+}
+
+#[derive(Deserialize)]
+struct BatchList {
+    data: Vec<MessageBatch>,
+}
+
+#[derive(Deserialize)]
+struct RawBatchResult {
+    custom_id: String,
+    result: RawOutcome,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RawOutcome {
+    Succeeded { message: ClaudeResponse },
+    Errored { error: ApiErrorBody },
+    Canceled,
+    Expired,
+}
+
+impl From<RawBatchResult> for BatchResult {
+    fn from(raw: RawBatchResult) -> Self {
+        let outcome = match raw.result {
+            RawOutcome::Succeeded { message } => BatchOutcome::Succeeded(message),
+            RawOutcome::Errored { error } => BatchOutcome::Errored {
+                error_type: error.error.error_type,
+                message: error.error.message,
+            },
+            RawOutcome::Canceled => BatchOutcome::Canceled,
+            RawOutcome::Expired => BatchOutcome::Expired,
+        };
+
+        BatchResult {
+            custom_id: raw.custom_id,
+            outcome,
+        }
+    }
+}
+
+impl Client {
+    /// Submits requests to be processed as a batch, each tagged with a unique custom id.
+    pub async fn create_batch(
+        &self,
+        requests: Vec<(String, ClaudeRequest)>,
+    ) -> Result<MessageBatch> {
+        let mut betas: Vec<&str> = Vec::new();
+        for (_, request) in &requests {
+            for beta in request.betas() {
+                if !betas.contains(&beta) {
+                    betas.push(beta);
+                }
+            }
+        }
+
+        let requests: Vec<_> = requests
+            .into_iter()
+            .map(|(custom_id, params)| json!({"custom_id": custom_id, "params": params}))
+            .collect();
+        let body = json!({ "requests": requests }).to_string();
+
+        let response = self.post("/v1/messages/batches", body, &betas).await?;
+        self.read_json(response).await
+    }
+
+    /// Retrieves the current state of a batch.
+    pub async fn get_batch(&self, id: &str) -> Result<MessageBatch> {
+        let response = self.get(&format!("/v1/messages/batches/{}", id)).await?;
+        self.read_json(response).await
+    }
+
+    /// Lists the most recently created batches.
+    pub async fn list_batches(&self) -> Result<Vec<MessageBatch>> {
+        let response = self.get("/v1/messages/batches").await?;
+        let list: BatchList = self.read_json(response).await?;
+
+        Ok(list.data)
+    }
+
+    /// Streams the results of an ended batch, one per request.
+    ///
+    /// Results are not guaranteed to be in the order the requests were submitted; match them
+    /// up using [`BatchResult::custom_id`].
+    pub async fn batch_results(&self, id: &str) -> Result<impl Stream<Item = Result<BatchResult>>> {
+        let response = self
+            .get(&format!("/v1/messages/batches/{}/results", id))
+            .await?;
+
+        Ok(decode_lines(response.bytes_stream()))
+    }
+}
+
+/// Decodes a stream of JSONL body chunks into [`BatchResult`]s.
+fn decode_lines<S, B, E>(bytes: S) -> impl Stream<Item = Result<BatchResult>>
+where
+    S: Stream<Item = std::result::Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<Error>,
+{
+    struct State<S> {
+        bytes: std::pin::Pin<Box<S>>,
+        buffer: Vec<u8>,
+        done: bool,
+    }
+
+    let state = State {
+        bytes: Box::pin(bytes),
+        buffer: Vec::new(),
+        done: false,
+    };
+
+    futures::stream::unfold(state, |mut state| async move {
+        loop {
+            let line = match state.buffer.iter().position(|&b| b == b'\n') {
+                Some(end) => Some(state.buffer.drain(..end + 1).collect::<Vec<u8>>()),
+                None if state.done && !state.buffer.is_empty() => {
+                    Some(std::mem::take(&mut state.buffer))
+                }
+                None if state.done => return None,
+                None => None,
+            };
+
+            if let Some(line) = line {
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                let result = serde_json::from_slice::<RawBatchResult>(&line)
+                    .map(BatchResult::from)
+                    .map_err(Error::from);
+                return Some((result, state));
+            }
+
+            match state.bytes.next().await {
+                Some(Ok(chunk)) => state.buffer.extend_from_slice(chunk.as_ref()),
+                Some(Err(e)) => {
+                    state.done = true;
+                    state.buffer.clear();
+                    return Some((Err(e.into()), state));
+                }
+                None => state.done = true,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::message_json;
+    use crate::{ContentType, Model, Role};
+    use futures::TryStreamExt;
+    use wiremock::matchers::{body_partial_json, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn request(text: &str) -> ClaudeRequest {
+        ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: text.to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(100)
+            .build()
+            .expect("Failed to build request")
+    }
+
+    #[tokio::test]
+    async fn test_create_batch_and_read_results() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages/batches"))
+            .and(body_partial_json(json!({
+                "requests": [
+                    {"custom_id": "call-1", "params": {"model": "claude-3-haiku-20240307"}},
+                    {"custom_id": "call-2", "params": {"max_tokens": 100}}
+                ]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "id": "msgbatch_01",
+                "type": "message_batch",
+                "processing_status": "in_progress",
+                "request_counts": {
+                    "processing": 2,
+                    "succeeded": 0,
+                    "errored": 0,
+                    "canceled": 0,
+                    "expired": 0
+                },
+                "created_at": "2024-09-24T18:37:24.100435Z",
+                "expires_at": "2024-09-25T18:37:24.100435Z",
+                "ended_at": null,
+                "cancel_initiated_at": null,
+                "results_url": null
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let results = [
+            json!({
+                "custom_id": "call-1",
+                "result": {
+                    "type": "succeeded",
+                    "message": message_json(
+                        json!([{"type": "text", "text": "Revenue grew 12%."}]),
+                        "end_turn",
+                    )
+                }
+            }),
+            json!({
+                "custom_id": "call-2",
+                "result": {
+                    "type": "errored",
+                    "error": {
+                        "type": "error",
+                        "error": {"type": "invalid_request_error", "message": "Bad request"}
+                    }
+                }
+            }),
+        ];
+        let body = format!("{}\n{}\n", results[0], results[1]);
+        Mock::given(method("GET"))
+            .and(path("/v1/messages/batches/msgbatch_01/results"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(body))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let batch = client
+            .create_batch(vec![
+                ("call-1".to_string(), request("Summarize the first call")),
+                ("call-2".to_string(), request("Summarize the second call")),
+            ])
+            .await?;
+
+        assert_eq!(batch.id, "msgbatch_01");
+        assert_eq!(batch.processing_status, BatchStatus::InProgress);
+        assert_eq!(batch.request_counts.processing, 2);
+
+        let results: Vec<BatchResult> =
+            client.batch_results(&batch.id).await?.try_collect().await?;
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].custom_id, "call-1");
+        assert!(matches!(
+            &results[0].outcome,
+            BatchOutcome::Succeeded(response) if response.id == "msg_01"
+        ));
+        assert_eq!(results[1].custom_id, "call-2");
+        assert!(matches!(
+            &results[1].outcome,
+            BatchOutcome::Errored { error_type, .. } if error_type == "invalid_request_error"
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,1392 @@
+//! HTTP client for the Claude API.
+
+use crate::clock::{Clock, TokioClock};
+use crate::rate_limit::{Limiter, RateLimit, RateLimitInfo, RateLimiter};
+use crate::retry::{self, RetryPolicy};
+use crate::stream::{self, StreamEvent};
+use crate::transport::{self, Transport};
+use crate::{ClaudeRequest, ClaudeRequestBuilder, ClaudeResponse, Error, Model, Result};
+use futures::{Stream, StreamExt};
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use reqwest::Method;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+const API_KEY_ENV: &str = "ANTHROPIC_API_KEY";
+const DEFAULT_BASE_URL: &str = "https://api.anthropic.com";
+const DEFAULT_API_VERSION: &str = "2023-06-01";
+
+/// Characters of an unexpected response body kept in [`Error::UnexpectedContentType`].
+const BODY_SNIPPET_CHARS: usize = 200;
+
+/// Query parameters the API reads itself, which the client may not be given.
+///
+/// `beta` switches endpoints to their beta versions, which this client selects with the
+/// `anthropic-beta` header instead. The others page through lists, which
+/// [`Client::list_batches`] relies on getting from the start.
+const RESERVED_QUERY_KEYS: &[&str] = &["beta", "limit", "before_id", "after_id"];
+
+/// Request fields accepted by the token-counting endpoint.
+const COUNT_TOKENS_FIELDS: &[&str] = &[
+    "model",
+    "messages",
+    "system",
+    "tools",
+    "tool_choice",
+    "thinking",
+];
+
+#[derive(Deserialize)]
+struct CountTokensResponse {
+    input_tokens: u32,
+}
+
+/// Client used to send requests to the Claude API.
+#[derive(Clone)]
+pub struct Client {
+    api_key: Option<String>,
+    /// Environment variable the API key is read from when none is set.
+    api_key_var: &'static str,
+    base_url: String,
+    api_version: String,
+    model: Option<Model>,
+    retry: Option<RetryPolicy>,
+    limiter: Option<Arc<Limiter>>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+    query: Vec<(String, String)>,
+    browser_access: bool,
+    raw_response: bool,
+    max_body_size: Option<usize>,
+    timeout: Option<Duration>,
+    http: reqwest::Client,
+    transport: Option<Arc<dyn Transport>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Client {
+            api_key: None,
+            api_key_var: API_KEY_ENV,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            api_version: DEFAULT_API_VERSION.to_string(),
+            model: None,
+            retry: None,
+            limiter: None,
+            rate_limiter: None,
+            query: Vec::new(),
+            browser_access: false,
+            raw_response: false,
+            max_body_size: None,
+            timeout: None,
+            http: reqwest::Client::new(),
+            transport: None,
+            clock: Arc::new(TokioClock),
+        }
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .field("api_key_var", &self.api_key_var)
+            .field("base_url", &self.base_url)
+            .field("api_version", &self.api_version)
+            .field("model", &self.model)
+            .field("retry", &self.retry)
+            .field("limiter", &self.limiter)
+            .field("rate_limiter", &self.rate_limiter)
+            .field("query", &self.query)
+            .field("browser_access", &self.browser_access)
+            .field("raw_response", &self.raw_response)
+            .field("max_body_size", &self.max_body_size)
+            .field("timeout", &self.timeout)
+            .field("http", &self.http)
+            .field("transport", &self.transport)
+            .field("clock", &self.clock)
+            .finish()
+    }
+}
+
+/// Builder for creating a [`Client`].
+///
+/// Each method applies the matching `with_*` setter of [`Client`], which documents it.
+#[derive(Debug, Clone, Default)]
+pub struct ClientBuilder {
+    client: Client,
+}
+
+impl ClientBuilder {
+    /// Creates a new ClientBuilder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the API key, instead of reading `ANTHROPIC_API_KEY`.
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.client.api_key = Some(api_key.into());
+        self
+    }
+
+    /// See [`Client::with_base_url`].
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.client = self.client.with_base_url(base_url);
+        self
+    }
+
+    /// See [`Client::with_api_version`].
+    pub fn api_version(mut self, api_version: impl Into<String>) -> Self {
+        self.client = self.client.with_api_version(api_version);
+        self
+    }
+
+    /// See [`Client::with_model`].
+    pub fn model(mut self, model: Model) -> Self {
+        self.client = self.client.with_model(model);
+        self
+    }
+
+    /// See [`Client::with_retry`].
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.client = self.client.with_retry(retry);
+        self
+    }
+
+    /// See [`Client::with_rate_limit`].
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.client = self.client.with_rate_limit(rate_limit);
+        self
+    }
+
+    /// See [`Client::with_query_param`].
+    pub fn query_param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.client = self.client.with_query_param(key, value);
+        self
+    }
+
+    /// See [`Client::with_browser_access`].
+    pub fn allow_browser_access(mut self) -> Self {
+        self.client = self.client.with_browser_access();
+        self
+    }
+
+    /// See [`Client::with_raw_response`].
+    pub fn keep_raw_response(mut self) -> Self {
+        self.client = self.client.with_raw_response();
+        self
+    }
+
+    /// See [`Client::with_max_body_size`].
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.client = self.client.with_max_body_size(max_body_size);
+        self
+    }
+
+    /// See [`Client::with_timeout`].
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.client = self.client.with_timeout(timeout);
+        self
+    }
+
+    /// See [`Client::with_connect_timeout`].
+    pub fn connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.client = self.client.with_connect_timeout(connect_timeout);
+        self
+    }
+
+    /// See [`Client::with_transport`].
+    pub fn transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.client = self.client.with_transport(transport);
+        self
+    }
+
+    /// See [`Client::with_clock`].
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.client = self.client.with_clock(clock);
+        self
+    }
+
+    /// Builds the Client.
+    pub fn build(self) -> Client {
+        self.client
+    }
+}
+
+/// Settings that apply to a single call, overriding the client's.
+#[derive(Clone, Default)]
+pub struct CallOptions {
+    /// Query parameters added to the request URL, replacing client defaults with the same key.
+    pub query: Vec<(String, String)>,
+    /// API key sent instead of the client's, e.g. one per tenant of a shared client.
+    pub api_key: Option<String>,
+}
+
+impl fmt::Debug for CallOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CallOptions")
+            .field("query", &self.query)
+            .field("api_key", &self.api_key.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl CallOptions {
+    /// Creates empty CallOptions, which leave the client's settings as they are.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a query parameter to the request URL.
+    pub fn query_param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query.push((key.into(), value.into()));
+        self
+    }
+
+    /// Sends the call with `api_key` instead of the client's key.
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.api_key = Some(api_key.into());
+        self
+    }
+}
+
+/// A response together with what its headers say about the call.
+#[derive(Debug, Clone)]
+pub struct ResponseEnvelope {
+    pub response: ClaudeResponse,
+    /// The `request-id` header, which Anthropic support asks for when investigating a call.
+    pub request_id: Option<String>,
+    pub rate_limit: RateLimitInfo,
+}
+
+/// Creates the underlying HTTP client.
+fn http_client(connect_timeout: Option<Duration>) -> reqwest::Client {
+    let mut builder = reqwest::Client::builder();
+    if let Some(connect_timeout) = connect_timeout {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+    builder.build().expect("Failed to build HTTP client")
+}
+
+impl Client {
+    /// Creates a new Client that reads `ANTHROPIC_API_KEY` when a request is sent.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new ClientBuilder to configure a client.
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Creates a new Client with an explicit API key, ignoring the environment.
+    pub fn with_api_key(api_key: impl Into<String>) -> Self {
+        Client {
+            api_key: Some(api_key.into()),
+            ..Self::default()
+        }
+    }
+
+    /// Uses `model` for requests started with [`messages`](Self::messages).
+    pub fn with_model(mut self, model: Model) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Starts building a request, using the client's default model if it has one.
+    ///
+    /// Calling [`model`](ClaudeRequestBuilder::model) on the builder overrides the default.
+    pub fn messages(&self) -> ClaudeRequestBuilder {
+        let builder = ClaudeRequest::builder();
+        match &self.model {
+            Some(model) => builder.model(model.clone()),
+            None => builder,
+        }
+    }
+
+    /// Retries rate-limited and overloaded requests according to `policy`.
+    pub fn with_retry(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Paces message requests to stay under `rate_limit`.
+    ///
+    /// See [`RateLimit`].
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.limiter = Some(Arc::new(Limiter::new(&rate_limit)));
+        self
+    }
+
+    /// Holds requests back while the API reports the account is out of requests.
+    ///
+    /// See [`RateLimiter`].
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.rate_limiter = Some(Arc::new(rate_limiter));
+        self
+    }
+
+    /// Fails requests that take longer than `timeout` with [`Error::Timeout`].
+    ///
+    /// The timeout runs from sending a request until its response body is read. For
+    /// streamed responses it bounds the whole stream.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Fails requests that cannot connect to the API within `connect_timeout`.
+    pub fn with_connect_timeout(mut self, connect_timeout: Duration) -> Self {
+        self.http = http_client(Some(connect_timeout));
+        self
+    }
+
+    /// Adds a query parameter to the URL of every request, e.g. a gateway deployment id.
+    ///
+    /// Requests fail with [`Error::InvalidQueryParam`] if the key is empty or one the API
+    /// reads itself, such as `beta` or `limit`.
+    pub fn with_query_param(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query.push((key.into(), value.into()));
+        self
+    }
+
+    /// Allows calling the API directly from a browser, e.g. from WASM.
+    ///
+    /// This sends the `anthropic-dangerous-direct-browser-access` header. Anything running in
+    /// a browser can be inspected by its user, so the API key is exposed to whoever loads the
+    /// page. Only enable this with keys you are willing to hand out, such as a user's own key.
+    pub fn with_browser_access(mut self) -> Self {
+        self.browser_access = true;
+        self
+    }
+
+    /// Keeps the body of each message response in [`ClaudeResponse::raw`], e.g. for logging
+    /// exactly what was parsed.
+    pub fn with_raw_response(mut self) -> Self {
+        self.raw_response = true;
+        self
+    }
+
+    /// Sets the largest response body, in bytes, that will be read into memory.
+    ///
+    /// Reading stops with [`Error::ResponseTooLarge`] once a body grows past the limit. For
+    /// streamed responses the limit applies to the whole stream.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = Some(max_body_size);
+        self
+    }
+
+    /// Sends requests through `transport` instead of over HTTP, e.g. a `MockTransport` from
+    /// the `testing` feature.
+    pub fn with_transport(mut self, transport: Arc<dyn Transport>) -> Self {
+        self.transport = Some(transport);
+        self
+    }
+
+    /// Waits for retries and rate limits on `clock`, e.g. a `MockClock` from the `testing`
+    /// feature, instead of the Tokio clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Sends requests to a different host, e.g. a proxy or gateway.
+    ///
+    /// Defaults to `https://api.anthropic.com`. API paths such as `/v1/messages` are appended.
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into().trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Sends `api_version` in the `anthropic-version` header instead of `2023-06-01`.
+    pub fn with_api_version(mut self, api_version: impl Into<String>) -> Self {
+        self.api_version = api_version.into();
+        self
+    }
+
+    /// Resolves the API key, preferring the explicit key over the environment.
+    fn api_key(&self) -> Result<String> {
+        match &self.api_key {
+            Some(api_key) => Ok(api_key.clone()),
+            None => std::env::var(self.api_key_var).map_err(|_| Error::MissingApiKey),
+        }
+    }
+
+    /// Sends the request to the messages endpoint and checks the response status.
+    ///
+    /// Waits first if the client's [`RateLimit`] has no capacity left.
+    async fn send(
+        &self,
+        request: &ClaudeRequest,
+        options: &CallOptions,
+    ) -> Result<reqwest::Response> {
+        let body = serde_json::to_string(request)?;
+        let input_tokens = request.estimated_input_tokens();
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire(input_tokens, self.clock.as_ref()).await;
+        }
+
+        let betas = request.betas();
+        let response = self.execute(Method::POST, "/v1/messages", Some(body), &betas, options);
+        #[cfg(feature = "tracing")]
+        let response = crate::trace::instrument(request, input_tokens, response);
+        response.await
+    }
+
+    /// Posts a JSON body to `path`, retrying according to the client's [`RetryPolicy`].
+    ///
+    /// `betas` are sent in the `anthropic-beta` header when not empty.
+    pub(crate) async fn post(
+        &self,
+        path: &str,
+        body: String,
+        betas: &[&str],
+    ) -> Result<reqwest::Response> {
+        self.execute(
+            Method::POST,
+            path,
+            Some(body),
+            betas,
+            &CallOptions::default(),
+        )
+        .await
+    }
+
+    /// Sends a GET request to `path`, retrying according to the client's [`RetryPolicy`].
+    pub(crate) async fn get(&self, path: &str) -> Result<reqwest::Response> {
+        self.execute(Method::GET, path, None, &[], &CallOptions::default())
+            .await
+    }
+
+    async fn execute(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<String>,
+        betas: &[&str],
+        options: &CallOptions,
+    ) -> Result<reqwest::Response> {
+        let api_key = match &options.api_key {
+            Some(api_key) => api_key.clone(),
+            None => self.api_key()?,
+        };
+        let query = self.query(options)?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        headers.insert(
+            "anthropic-version",
+            HeaderValue::from_str(&self.api_version)
+                .map_err(|_| Error::InvalidHeader("anthropic-version".to_string()))?,
+        );
+        headers.insert(
+            "x-api-key",
+            HeaderValue::from_str(&api_key).map_err(|_| Error::InvalidApiKey)?,
+        );
+        if !betas.is_empty() {
+            headers.insert(
+                "anthropic-beta",
+                HeaderValue::from_str(&betas.join(","))
+                    .map_err(|_| Error::InvalidHeader("anthropic-beta".to_string()))?,
+            );
+        }
+        if self.browser_access {
+            headers.insert(
+                "anthropic-dangerous-direct-browser-access",
+                HeaderValue::from_static("true"),
+            );
+        }
+
+        let mut attempt = 1;
+        loop {
+            let mut builder = self
+                .http
+                .request(method.clone(), format!("{}{}", self.base_url, path))
+                .headers(headers.clone())
+                .query(&query);
+            if let Some(body) = &body {
+                builder = builder.body(body.clone());
+            }
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.acquire(self.clock.as_ref()).await;
+            }
+            let response = match &self.transport {
+                Some(transport) => {
+                    transport::send(transport.as_ref(), builder.build()?, self.timeout).await?
+                }
+                None => {
+                    if let Some(timeout) = self.timeout {
+                        builder = builder.timeout(timeout);
+                    }
+                    builder.send().await?
+                }
+            };
+
+            if let Some(rate_limiter) = &self.rate_limiter {
+                rate_limiter.observe(response.headers(), self.clock.as_ref());
+            }
+            let status = response.status();
+
+            if status.is_success() {
+                return Ok(response);
+            }
+
+            let retry_after = retry::retry_after(response.headers());
+            let error = match self.read_json_body(response).await {
+                Ok(text) => Error::from_response(status, &text),
+                Err(error @ Error::UnexpectedContentType { .. }) => error,
+                Err(error) => return Err(error),
+            };
+
+            match &self.retry {
+                Some(policy) if policy.should_retry(status, attempt) => {
+                    self.clock.sleep(policy.delay(attempt, retry_after)).await;
+                    attempt += 1;
+                }
+                _ => return Err(error),
+            }
+        }
+    }
+
+    /// Merges the client's query parameters with those of the call, which take precedence.
+    ///
+    /// Keys may not be empty or one of [`RESERVED_QUERY_KEYS`].
+    fn query<'a>(&'a self, options: &'a CallOptions) -> Result<Vec<&'a (String, String)>> {
+        let mut query: Vec<&(String, String)> = Vec::new();
+        for param in self.query.iter().chain(&options.query) {
+            let key = param.0.as_str();
+            if key.is_empty() || RESERVED_QUERY_KEYS.contains(&key) {
+                return Err(Error::InvalidQueryParam(key.to_string()));
+            }
+            query.retain(|(existing, _)| existing != key);
+            query.push(param);
+        }
+
+        Ok(query)
+    }
+
+    /// Reads the response body as JSON, enforcing the client's maximum body size.
+    ///
+    /// Bodies whose `Content-Type` is not JSON, such as error pages from a proxy, are
+    /// returned as [`Error::UnexpectedContentType`] rather than failing to parse.
+    pub(crate) async fn read_json<T: DeserializeOwned>(
+        &self,
+        response: reqwest::Response,
+    ) -> Result<T> {
+        Ok(serde_json::from_str(&self.read_json_body(response).await?)?)
+    }
+
+    /// Reads a response body that should be JSON, without parsing it.
+    ///
+    /// Used for error responses too, so a proxy's error page is not kept whole in
+    /// [`Error::Api`].
+    async fn read_json_body(&self, response: reqwest::Response) -> Result<String> {
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+        let text = self.read_body(response).await?;
+
+        if let Some(content_type) = content_type {
+            if !content_type.to_ascii_lowercase().contains("json") {
+                return Err(Error::UnexpectedContentType {
+                    got: content_type,
+                    body_snippet: text.chars().take(BODY_SNIPPET_CHARS).collect(),
+                });
+            }
+        }
+
+        Ok(text)
+    }
+
+    /// Reads the response body, enforcing the client's maximum body size.
+    pub(crate) async fn read_body(&self, mut response: reqwest::Response) -> Result<String> {
+        let Some(limit) = self.max_body_size else {
+            return Ok(response.text().await?);
+        };
+
+        if response
+            .content_length()
+            .is_some_and(|length| length > limit as u64)
+        {
+            return Err(Error::ResponseTooLarge { limit });
+        }
+
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await? {
+            if body.len() + chunk.len() > limit {
+                return Err(Error::ResponseTooLarge { limit });
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+
+    /// Invoke the Claude Chat API.
+    ///
+    /// The request is never sent with `stream: true`; use [`call_stream`](Self::call_stream)
+    /// to stream the response.
+    pub async fn call(&self, request: &ClaudeRequest) -> Result<ClaudeResponse> {
+        self.call_with_options(request, &CallOptions::default())
+            .await
+    }
+
+    /// Invoke the Claude Chat API with settings that apply to this call only.
+    pub async fn call_with_options(
+        &self,
+        request: &ClaudeRequest,
+        options: &CallOptions,
+    ) -> Result<ClaudeResponse> {
+        Ok(self.call_enveloped(request, options).await?.response)
+    }
+
+    /// Invoke the Claude Chat API, keeping the request id and rate limits from the headers.
+    pub async fn call_with_headers(&self, request: &ClaudeRequest) -> Result<ResponseEnvelope> {
+        self.call_enveloped(request, &CallOptions::default()).await
+    }
+
+    /// Invoke the Claude Chat API, falling back to the next of `fallback_models` while the
+    /// request is rate limited or the model is overloaded.
+    ///
+    /// Each model is first retried according to the client's [`RetryPolicy`]. Any other error
+    /// is returned at once, as is the last model's error. The model that answered is the
+    /// response's [`model`](ClaudeResponse::model).
+    pub async fn call_with_fallback(
+        &self,
+        request: &ClaudeRequest,
+        fallback_models: &[Model],
+    ) -> Result<ClaudeResponse> {
+        let mut result = self.call(request).await;
+        for model in fallback_models {
+            match &result {
+                Err(Error::Api { status, .. }) if retry::is_retryable(*status) => {
+                    result = self.call(&request.with_model(model.clone())).await;
+                }
+                _ => break,
+            }
+        }
+
+        result
+    }
+
+    async fn call_enveloped(
+        &self,
+        request: &ClaudeRequest,
+        options: &CallOptions,
+    ) -> Result<ResponseEnvelope> {
+        let response = if request.stream == Some(true) {
+            let mut request = request.clone();
+            request.stream = Some(false);
+            self.send(&request, options).await?
+        } else {
+            self.send(request, options).await?
+        };
+
+        let headers = response.headers();
+        let request_id = headers
+            .get("request-id")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let rate_limit = RateLimitInfo::from_headers(headers);
+
+        let body = self.read_json_body(response).await?;
+        let mut response: ClaudeResponse = serde_json::from_str(&body)?;
+        if self.raw_response {
+            response.raw = Some(body);
+        }
+
+        Ok(ResponseEnvelope {
+            response,
+            request_id,
+            rate_limit,
+        })
+    }
+
+    /// Invoke the Claude Chat API and stream the response as it is generated.
+    ///
+    /// The request is always sent with `stream: true`.
+    pub async fn call_stream(
+        &self,
+        request: &ClaudeRequest,
+    ) -> Result<impl Stream<Item = Result<StreamEvent>>> {
+        let mut request = request.clone();
+        request.stream = Some(true);
+
+        let response = self.send(&request, &CallOptions::default()).await?;
+
+        Ok(stream::decode(response.bytes_stream(), self.max_body_size))
+    }
+
+    /// Streams the response and assembles it into a complete [`ClaudeResponse`].
+    ///
+    /// The result is the same as [`call`](Self::call), but the response is generated as a
+    /// stream, which avoids idle connections timing out on long responses. If the server
+    /// closes the stream between content blocks, the blocks received so far are returned with
+    /// [`incomplete`](ClaudeResponse::incomplete) set.
+    pub async fn call_stream_collect(&self, request: &ClaudeRequest) -> Result<ClaudeResponse> {
+        stream::collect(self.call_stream(request).await?).await
+    }
+
+    /// Streams the response into a bounded channel from a background task.
+    ///
+    /// The task waits while the channel is full, so a slow consumer applies backpressure to
+    /// the HTTP stream. The channel closes after `message_stop`, after the first error, or
+    /// when the response ends. Must be called from within a Tokio runtime.
+    pub async fn call_stream_channel(
+        &self,
+        request: &ClaudeRequest,
+        buffer: usize,
+    ) -> Result<mpsc::Receiver<Result<StreamEvent>>> {
+        let events = self.call_stream(request).await?;
+        let (sender, receiver) = mpsc::channel(buffer);
+
+        tokio::spawn(async move {
+            let mut events = std::pin::pin!(events);
+            while let Some(event) = events.next().await {
+                let last = matches!(event, Ok(StreamEvent::MessageStop) | Err(_));
+                if sender.send(event).await.is_err() || last {
+                    break;
+                }
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    /// Streams the response to `writer` as newline-delimited JSON, one event per line.
+    ///
+    /// Events are written in the shape the API sends them, so the output can be piped to
+    /// other tools, e.g. `jq`. Writing stops after `message_stop` or at the first error.
+    pub async fn call_stream_ndjson(
+        &self,
+        request: &ClaudeRequest,
+        writer: impl std::io::Write,
+    ) -> Result<()> {
+        stream::write_ndjson(self.call_stream(request).await?, writer).await
+    }
+
+    /// Counts the input tokens `request` would use, without creating a message.
+    ///
+    /// Only the fields the counting endpoint understands (model, messages, system, tools, tool
+    /// choice and thinking) are sent; settings such as `max_tokens` are dropped.
+    pub async fn count_tokens(&self, request: &ClaudeRequest) -> Result<u32> {
+        let mut body = serde_json::to_value(request)?;
+        if let Value::Object(fields) = &mut body {
+            fields.retain(|key, _| COUNT_TOKENS_FIELDS.contains(&key.as_str()));
+        }
+
+        let response = self
+            .post(
+                "/v1/messages/count_tokens",
+                body.to_string(),
+                &request.betas(),
+            )
+            .await?;
+        let count: CountTokensResponse = self.read_json(response).await?;
+
+        Ok(count.input_tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::message_json;
+    use crate::{ContentType, Model, Role};
+    use serde_json::json;
+    use wiremock::matchers::{body_json, body_partial_json, header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    async fn test_missing_api_key_is_an_error() {
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Hello".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(10)
+            .build()
+            .expect("Failed to build request");
+
+        // Read an unset variable, so the result does not depend on the environment.
+        let client = Client {
+            api_key_var: "TYRELL_TEST_UNSET_API_KEY",
+            ..Client::new()
+        };
+        let err = client.call(&request).await.unwrap_err();
+
+        assert!(matches!(err, Error::MissingApiKey));
+    }
+
+    #[test]
+    fn test_debug_redacts_api_key() {
+        let client = Client::with_api_key("sk-ant-secret");
+        let debug = format!("{:?}", client);
+
+        assert!(!debug.contains("sk-ant-secret"));
+        assert!(debug.contains(r#"api_key: Some("<redacted>")"#));
+        assert!(!format!("{:?}", Client::builder().api_key("sk-ant-secret")).contains("secret"));
+    }
+
+    fn request() -> ClaudeRequest {
+        ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Hello".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(10)
+            .build()
+            .expect("Failed to build request")
+    }
+
+    fn fast_retry() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+            jitter: 0.0,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_retries_overloaded_until_success() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(529).set_body_json(json!({
+                "type": "error",
+                "error": {"type": "overloaded_error", "message": "Overloaded"}
+            })))
+            .up_to_n_times(2)
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(message_json(
+                json!([{"type": "text", "text": "Hello!"}]),
+                "end_turn",
+            )))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key")
+            .with_base_url(server.uri())
+            .with_retry(fast_retry());
+        let response = client.call(&request()).await?;
+
+        assert_eq!(response.id, "msg_01");
+        assert_eq!(server.received_requests().await.unwrap().len(), 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bad_request_is_not_retried() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+                "type": "error",
+                "error": {"type": "invalid_request_error", "message": "Bad request"}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key")
+            .with_base_url(server.uri())
+            .with_retry(fast_retry());
+        let err = client.call(&request()).await.unwrap_err();
+
+        assert!(matches!(
+            err,
+            Error::Api { ref error_type, .. } if error_type == "invalid_request_error"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_browser_access_header_only_when_enabled() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(message_json(
+                json!([{"type": "text", "text": "Hello!"}]),
+                "end_turn",
+            )))
+            .mount(&server)
+            .await;
+
+        let default_client = Client::builder()
+            .api_key("test-key")
+            .build()
+            .with_base_url(server.uri());
+        let browser_client = Client::builder()
+            .api_key("test-key")
+            .allow_browser_access()
+            .build()
+            .with_base_url(server.uri());
+
+        default_client.call(&request()).await?;
+        browser_client.call(&request()).await?;
+
+        let requests = server.received_requests().await.unwrap();
+        let header = "anthropic-dangerous-direct-browser-access";
+        assert!(requests[0].headers.get(header).is_none());
+        assert_eq!(requests[1].headers.get(header).unwrap(), "true");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_body_over_max_size_is_rejected() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("x".repeat(2048)))
+            .mount(&server)
+            .await;
+
+        let client = Client::builder()
+            .api_key("test-key")
+            .max_body_size(1024)
+            .build()
+            .with_base_url(server.uri());
+        let err = client.call(&request()).await.unwrap_err();
+
+        assert!(matches!(err, Error::ResponseTooLarge { limit: 1024 }));
+    }
+
+    #[tokio::test]
+    async fn test_stream_over_max_size_is_rejected() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "text/event-stream")
+                    .set_body_string(include_str!("../tests/fixtures/stream_tool_use.sse")),
+            )
+            .mount(&server)
+            .await;
+
+        let client = Client::builder()
+            .api_key("test-key")
+            .max_body_size(256)
+            .build()
+            .with_base_url(server.uri());
+        let err = client.call_stream_collect(&request()).await.unwrap_err();
+
+        assert!(matches!(err, Error::ResponseTooLarge { limit: 256 }));
+    }
+
+    #[tokio::test]
+    async fn test_html_response_is_an_unexpected_content_type() {
+        let page = format!(
+            "<html><head><title>Gateway</title></head><body>{}</body></html>",
+            "Upstream unavailable. ".repeat(20)
+        );
+        // Both a successful response and a load balancer's error page.
+        for status in [200, 502] {
+            let server = MockServer::start().await;
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(status).set_body_raw(page.clone(), "text/html"))
+                .mount(&server)
+                .await;
+
+            let client = Client::with_api_key("test-key").with_base_url(server.uri());
+            let err = client.call(&request()).await.unwrap_err();
+
+            match err {
+                Error::UnexpectedContentType { got, body_snippet } => {
+                    assert_eq!(got, "text/html");
+                    assert!(body_snippet.starts_with("<html><head><title>Gateway</title>"));
+                    assert_eq!(body_snippet.chars().count(), BODY_SNIPPET_CHARS);
+                }
+                err => panic!(
+                    "expected UnexpectedContentType for {}, got {:?}",
+                    status, err
+                ),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cached_block_sends_prompt_caching_beta() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("anthropic-beta", "prompt-caching-2024-07-31"))
+            .and(body_partial_json(json!({
+                "messages": [{
+                    "role": "user",
+                    "content": [{
+                        "type": "text",
+                        "text": "A very long document",
+                        "cache_control": {"type": "ephemeral"}
+                    }]
+                }]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(message_json(
+                json!([{"type": "text", "text": "Hello!"}]),
+                "end_turn",
+            )))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let cached_request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "A very long document".to_string(),
+                    cache_control: None,
+                }
+                .cached()],
+            )
+            .max_tokens(10)
+            .build()
+            .expect("Failed to build request");
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        client.call(&cached_request).await?;
+
+        assert!(request().betas().is_empty());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_call_with_fallback() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(
+                json!({"model": "claude-3-haiku-20240307"}),
+            ))
+            .respond_with(ResponseTemplate::new(529).set_body_json(json!({
+                "type": "error",
+                "error": {"type": "overloaded_error", "message": "Overloaded"}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let mut fallback = message_json(json!([{"type": "text", "text": "Hello!"}]), "end_turn");
+        fallback["model"] = json!("claude-3-5-haiku-20241022");
+        Mock::given(method("POST"))
+            .and(body_partial_json(
+                json!({"model": "claude-3-5-haiku-20241022"}),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(fallback))
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let response = client
+            .call_with_fallback(&request(), &[Model::Haiku35])
+            .await?;
+
+        assert_eq!(response.model, Model::Haiku35);
+        assert_eq!(response.text(), "Hello!");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_call_with_headers() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("request-id", "req_018EeWyXxfu5pfWkrYcMdjWG")
+                    .insert_header("anthropic-ratelimit-requests-limit", "50")
+                    .insert_header("anthropic-ratelimit-requests-remaining", "49")
+                    .insert_header("anthropic-ratelimit-requests-reset", "1970-01-01T00:01:00Z")
+                    .insert_header("anthropic-ratelimit-tokens-remaining", "39000")
+                    .set_body_json(message_json(
+                        json!([{"type": "text", "text": "Hello!"}]),
+                        "end_turn",
+                    )),
+            )
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let envelope = client.call_with_headers(&request()).await?;
+
+        assert_eq!(envelope.response.id, "msg_01");
+        assert_eq!(
+            envelope.request_id.as_deref(),
+            Some("req_018EeWyXxfu5pfWkrYcMdjWG")
+        );
+        assert_eq!(
+            envelope.rate_limit.requests,
+            crate::LimitStatus {
+                limit: Some(50),
+                remaining: Some(49),
+                reset: Some(std::time::UNIX_EPOCH + Duration::from_secs(60)),
+            }
+        );
+        assert_eq!(envelope.rate_limit.tokens.remaining, Some(39000));
+        assert_eq!(
+            envelope.rate_limit.output_tokens,
+            crate::LimitStatus::default()
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_raw_response_is_kept_when_enabled() -> Result<()> {
+        let body =
+            message_json(json!([{"type": "text", "text": "Hello!"}]), "end_turn").to_string();
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(body.clone(), "application/json"))
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        assert_eq!(client.call(&request()).await?.raw, None);
+
+        let client = Client::builder()
+            .api_key("test-key")
+            .base_url(server.uri())
+            .keep_raw_response()
+            .build();
+        let response = client.call(&request()).await?;
+        assert_eq!(response.raw.as_deref(), Some(body.as_str()));
+        assert_eq!(response.text(), "Hello!");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_count_tokens() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages/count_tokens"))
+            .and(body_json(json!({
+                "model": "claude-3-haiku-20240307",
+                "messages": [{
+                    "role": "user",
+                    "content": [{"type": "text", "text": "Hello"}]
+                }]
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"input_tokens": 8})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let tokens = client.count_tokens(&request()).await?;
+
+        assert_eq!(tokens, 8);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_stream_channel_closes_after_message_stop() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"stream": true})))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-type", "text/event-stream")
+                    .set_body_string(include_str!("../tests/fixtures/stream_tool_use.sse")),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let mut receiver = client.call_stream_channel(&request(), 1).await?;
+
+        let mut events = Vec::new();
+        while let Some(event) = receiver.recv().await {
+            events.push(event?);
+        }
+
+        assert!(matches!(events[0], StreamEvent::MessageStart { .. }));
+        assert!(matches!(events.last(), Some(StreamEvent::MessageStop)));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_call_never_streams() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({"stream": false})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(message_json(
+                json!([{"type": "text", "text": "Hello!"}]),
+                "end_turn",
+            )))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut streaming = request();
+        streaming.stream = Some(true);
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let response = client.call(&streaming).await?;
+
+        assert_eq!(response.id, "msg_01");
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_slow_response_times_out() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_delay(Duration::from_secs(5)))
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key")
+            .with_base_url(server.uri())
+            .with_timeout(Duration::from_millis(50));
+        let err = client.call(&request()).await.unwrap_err();
+
+        assert!(matches!(err, Error::Timeout));
+    }
+
+    #[tokio::test]
+    async fn test_base_url_and_api_version_are_configurable() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages/count_tokens"))
+            .and(header("anthropic-version", "2024-01-01"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"input_tokens": 8})))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::builder()
+            .api_key("test-key")
+            .base_url(format!("{}/", server.uri()))
+            .api_version("2024-01-01")
+            .build();
+        client.count_tokens(&request()).await?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_default_model() {
+        let client = Client::builder().model(Model::Sonnet35).build();
+
+        let request = client
+            .messages()
+            .user("Hello")
+            .max_tokens(10)
+            .build()
+            .expect("Failed to build request");
+        assert_eq!(request.model, Model::Sonnet35);
+
+        let request = client
+            .messages()
+            .model(Model::Haiku3)
+            .user("Hello")
+            .max_tokens(10)
+            .build()
+            .expect("Failed to build request");
+        assert_eq!(request.model, Model::Haiku3);
+
+        let err = Client::new()
+            .messages()
+            .user("Hello")
+            .max_tokens(10)
+            .build()
+            .unwrap_err();
+        assert_eq!(err, crate::BuildError::MissingModel);
+    }
+
+    #[tokio::test]
+    async fn test_query_params_reach_the_server() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(query_param("api-version", "2024-06"))
+            .and(query_param("deployment", "eu-west"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(message_json(
+                json!([{"type": "text", "text": "Hello!"}]),
+                "end_turn",
+            )))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::builder()
+            .api_key("test-key")
+            .base_url(server.uri())
+            .query_param("api-version", "2024-06")
+            .query_param("deployment", "us-east")
+            .build();
+        let options = CallOptions::new().query_param("deployment", "eu-west");
+        client.call_with_options(&request(), &options).await?;
+
+        let requests = server.received_requests().await.unwrap();
+        assert_eq!(
+            requests[0].url.query(),
+            Some("api-version=2024-06&deployment=eu-west")
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_per_call_api_key() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(header("x-api-key", "tenant-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(message_json(
+                json!([{"type": "text", "text": "Hello!"}]),
+                "end_turn",
+            )))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("default-key").with_base_url(server.uri());
+        let options = CallOptions::new().api_key("tenant-key");
+        client.call_with_options(&request(), &options).await?;
+
+        assert!(!format!("{:?}", options).contains("tenant-key"));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_conflicting_query_params_are_rejected() {
+        let client = Client::with_api_key("test-key").with_query_param("limit", "10");
+        let err = client.list_batches().await.unwrap_err();
+        assert!(matches!(err, Error::InvalidQueryParam(key) if key == "limit"));
+
+        let client = Client::with_api_key("test-key");
+        let options = CallOptions::new().query_param("beta", "true");
+        let err = client
+            .call_with_options(&request(), &options)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidQueryParam(key) if key == "beta"));
+
+        let options = CallOptions::new().query_param("", "x");
+        let err = client
+            .call_with_options(&request(), &options)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, Error::InvalidQueryParam(key) if key.is_empty()));
+    }
+}
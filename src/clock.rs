@@ -0,0 +1,199 @@
+//! The source of time for waiting between requests.
+
+use futures::future::BoxFuture;
+use std::fmt;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Tells time and waits for a [`Client`](crate::Client).
+///
+/// Retry backoff and [`RateLimit`](crate::RateLimit) pacing go through the client's clock,
+/// which is [`TokioClock`] unless replaced with
+/// [`Client::with_clock`](crate::Client::with_clock), e.g. by a `MockClock` from the
+/// `testing` feature.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Waits for `duration` to pass.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// The real clock, as seen by the Tokio runtime.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+#[cfg(feature = "testing")]
+pub use mock::MockClock;
+
+#[cfg(feature = "testing")]
+mod mock {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Debug)]
+    struct State {
+        now: Instant,
+        sleeps: Vec<Duration>,
+    }
+
+    /// A [`Clock`] that only moves when told to, so waits can be checked without taking time.
+    ///
+    /// Sleeping returns at once, moving the clock forward by the requested duration and
+    /// recording it in [`sleeps`](Self::sleeps). [`advance`](Self::advance) simulates time
+    /// passing between calls.
+    #[derive(Debug)]
+    pub struct MockClock {
+        state: Mutex<State>,
+    }
+
+    impl Default for MockClock {
+        fn default() -> Self {
+            MockClock {
+                state: Mutex::new(State {
+                    now: Instant::now(),
+                    sleeps: Vec::new(),
+                }),
+            }
+        }
+    }
+
+    impl MockClock {
+        /// Creates a MockClock stopped at the current time.
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Moves the clock forward by `duration`.
+        pub fn advance(&self, duration: Duration) {
+            self.state.lock().expect("MockClock lock poisoned").now += duration;
+        }
+
+        /// Returns the durations slept so far, in order.
+        pub fn sleeps(&self) -> Vec<Duration> {
+            self.state
+                .lock()
+                .expect("MockClock lock poisoned")
+                .sleeps
+                .clone()
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            self.state.lock().expect("MockClock lock poisoned").now
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            let mut state = self.state.lock().expect("MockClock lock poisoned");
+            state.now += duration;
+            state.sleeps.push(duration);
+            Box::pin(futures::future::ready(()))
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_util::message_json;
+        use crate::{ClaudeRequest, Client, Model, RateLimit, Result, RetryPolicy};
+        use serde_json::json;
+        use std::sync::Arc;
+        use wiremock::matchers::method;
+        use wiremock::{Mock, MockServer, ResponseTemplate};
+
+        fn request() -> ClaudeRequest {
+            ClaudeRequest::builder()
+                .model(Model::Haiku3)
+                .user("Hello")
+                .max_tokens(10)
+                .build()
+                .expect("Failed to build request")
+        }
+
+        async fn mount_success(server: &MockServer) {
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(200).set_body_json(message_json(
+                    json!([{"type": "text", "text": "Hello!"}]),
+                    "end_turn",
+                )))
+                .mount(server)
+                .await;
+        }
+
+        #[tokio::test]
+        async fn test_retry_delays_with_mock_clock() -> Result<()> {
+            let server = MockServer::start().await;
+            Mock::given(method("POST"))
+                .respond_with(ResponseTemplate::new(529).set_body_json(json!({
+                    "type": "error",
+                    "error": {"type": "overloaded_error", "message": "Overloaded"}
+                })))
+                .up_to_n_times(3)
+                .mount(&server)
+                .await;
+            mount_success(&server).await;
+
+            let clock = Arc::new(MockClock::new());
+            let client = Client::with_api_key("test-key")
+                .with_base_url(server.uri())
+                .with_retry(RetryPolicy {
+                    max_attempts: 4,
+                    base_delay: Duration::from_secs(1),
+                    max_delay: Duration::from_secs(3),
+                    jitter: 0.0,
+                })
+                .with_clock(clock.clone());
+
+            let start = std::time::Instant::now();
+            client.call(&request()).await?;
+
+            assert_eq!(
+                clock.sleeps(),
+                vec![
+                    Duration::from_secs(1),
+                    Duration::from_secs(2),
+                    Duration::from_secs(3)
+                ]
+            );
+            assert!(start.elapsed() < Duration::from_secs(1));
+
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn test_rate_limit_waits_on_mock_clock() -> Result<()> {
+            let server = MockServer::start().await;
+            mount_success(&server).await;
+
+            let clock = Arc::new(MockClock::new());
+            let client = Client::with_api_key("test-key")
+                .with_base_url(server.uri())
+                .with_clock(clock.clone())
+                .with_rate_limit(RateLimit {
+                    requests: Some(1),
+                    window: Duration::from_secs(8),
+                    ..RateLimit::default()
+                });
+
+            client.call(&request()).await?;
+            clock.advance(Duration::from_secs(4));
+            client.call(&request()).await?;
+
+            // Half the request allowance has come back, the other half takes 4 more seconds.
+            assert_eq!(clock.sleeps(), vec![Duration::from_secs(4)]);
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Multi-turn conversations: sending turns and keeping the history in a shape the API accepts.
+
+use crate::{
+    check_tool_results, BuildError, ClaudeRequestBuilder, ClaudeResponse, Client, ContentType,
+    Message, Result, Role,
+};
+
+/// The messages exchanged so far in a conversation, e.g. the history of an agent loop.
+///
+/// A conversation created from a [`ClaudeRequestBuilder`] can also be continued with
+/// [`send`](Self::send), which uses the builder's model and settings for every turn and keeps
+/// the history up to date.
+#[derive(Debug, Clone, Default)]
+pub struct Conversation {
+    messages: Vec<Message>,
+    request: ClaudeRequestBuilder,
+    last_response: Option<ClaudeResponse>,
+}
+
+impl From<Vec<Message>> for Conversation {
+    fn from(messages: Vec<Message>) -> Self {
+        Conversation {
+            messages,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<ClaudeRequestBuilder> for Conversation {
+    /// Starts a conversation with the settings of `request`, whose messages begin the history.
+    fn from(mut request: ClaudeRequestBuilder) -> Self {
+        Conversation {
+            messages: std::mem::take(&mut request.messages),
+            request,
+            last_response: None,
+        }
+    }
+}
+
+impl Conversation {
+    /// Creates an empty Conversation.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the messages of the conversation, oldest first.
+    pub fn history(&self) -> &[Message] {
+        &self.messages
+    }
+
+    /// Returns the response to the last turn sent with [`send`](Self::send).
+    pub fn last_response(&self) -> Option<&ClaudeResponse> {
+        self.last_response.as_ref()
+    }
+
+    /// Adds a message to the end of the conversation.
+    pub fn push(&mut self, message: Message) {
+        self.messages.push(message);
+    }
+
+    /// Forgets the history, keeping the settings used to send turns.
+    pub fn reset(&mut self) {
+        self.messages.clear();
+        self.last_response = None;
+    }
+
+    /// Sends `text` as the next user turn and adds it and the reply to the history.
+    ///
+    /// If the request cannot be built or the call fails, the history is left as it was.
+    pub async fn send(
+        &mut self,
+        client: &Client,
+        text: impl Into<String>,
+    ) -> Result<&ClaudeResponse> {
+        let user = Message {
+            role: Role::User,
+            content: vec![ContentType::text(text)],
+        };
+        let request = self
+            .request
+            .clone()
+            .messages(self.messages.clone())
+            .add_message(user.role.clone(), user.content.clone())
+            .build()?;
+
+        let response = client.call(&request).await?;
+        self.messages.push(user);
+        self.messages.push(response.assistant_message());
+
+        Ok(self.last_response.insert(response))
+    }
+
+    /// Fixes what can be fixed in the conversation, then checks it as the API would.
+    ///
+    /// Messages without content are dropped and adjacent messages with the same role are
+    /// merged into one. The result must start with a user turn and every tool result must
+    /// answer a tool use of the assistant turn before it. When `strict` is set nothing is
+    /// fixed: an empty message or two turns in a row from the same role are errors too.
+    ///
+    /// Errors name the index of the offending message, or the id of the unmatched tool use.
+    pub fn normalize_and_validate(mut self, strict: bool) -> Result<Self, BuildError> {
+        let mut messages: Vec<Message> = Vec::with_capacity(self.messages.len());
+        for (index, message) in std::mem::take(&mut self.messages).into_iter().enumerate() {
+            if message.content.is_empty() {
+                if strict {
+                    return Err(BuildError::EmptyMessage(index));
+                }
+                continue;
+            }
+
+            match messages.last_mut() {
+                Some(previous) if previous.role == message.role => {
+                    if strict {
+                        return Err(BuildError::RepeatedRole(index));
+                    }
+                    previous.content.extend(message.content);
+                }
+                _ => messages.push(message),
+            }
+        }
+
+        match messages.first() {
+            None => return Err(BuildError::NoMessages),
+            Some(first) if first.role != Role::User => return Err(BuildError::FirstMessageNotUser),
+            Some(_) => {}
+        }
+        check_tool_results(&messages)?;
+
+        Ok(Conversation { messages, ..self })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::message_json;
+    use crate::{ClaudeRequest, Model, ToolResult, ToolUse};
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn message(role: Role, content: Vec<ContentType>) -> Message {
+        Message { role, content }
+    }
+
+    fn messy() -> Conversation {
+        Conversation::from(vec![
+            message(Role::User, vec![ContentType::text("What's the weather?")]),
+            message(Role::User, vec![ContentType::text("In Paris, please.")]),
+            message(Role::Assistant, vec![]),
+            message(
+                Role::Assistant,
+                vec![ContentType::ToolUse(ToolUse::new(
+                    "toolu_01",
+                    "get_weather",
+                    json!({"location": "Paris"}),
+                ))],
+            ),
+            message(
+                Role::User,
+                vec![ContentType::ToolResult(ToolResult::new("toolu_01", "18°C"))],
+            ),
+        ])
+    }
+
+    #[test]
+    fn test_normalize_messy_conversation() -> Result<(), BuildError> {
+        let conversation = messy().normalize_and_validate(false)?;
+
+        let roles: Vec<&Role> = conversation
+            .history()
+            .iter()
+            .map(|message| &message.role)
+            .collect();
+        assert_eq!(roles, vec![&Role::User, &Role::Assistant, &Role::User]);
+        assert_eq!(conversation.history()[0].content.len(), 2);
+
+        assert_eq!(
+            messy().normalize_and_validate(true).unwrap_err(),
+            BuildError::RepeatedRole(1)
+        );
+
+        Ok(())
+    }
+
+    fn reply(text: &str) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(message_json(
+            json!([{"type": "text", "text": text}]),
+            "end_turn",
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_send_two_turns() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({
+                "messages": [{"role": "user", "content": [{"type": "text", "text": "Hi, I'm Ada."}]}]
+            })))
+            .respond_with(reply("Hello Ada!"))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(body_partial_json(json!({
+                "system": "Be brief.",
+                "messages": [
+                    {"role": "user", "content": [{"type": "text", "text": "Hi, I'm Ada."}]},
+                    {"role": "assistant", "content": [{"type": "text", "text": "Hello Ada!"}]},
+                    {"role": "user", "content": [{"type": "text", "text": "What's my name?"}]}
+                ]
+            })))
+            .respond_with(reply("Ada."))
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let mut conversation = Conversation::from(
+            ClaudeRequest::builder()
+                .model(Model::Haiku3)
+                .system("Be brief.")
+                .max_tokens(100),
+        );
+
+        conversation.send(&client, "Hi, I'm Ada.").await?;
+        let response = conversation.send(&client, "What's my name?").await?;
+
+        assert_eq!(response.text(), "Ada.");
+        assert_eq!(conversation.history().len(), 4);
+        assert_eq!(conversation.history()[3].role, Role::Assistant);
+
+        conversation.reset();
+        assert!(conversation.history().is_empty());
+        assert!(conversation.last_response().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validation_errors_are_specific() {
+        let mut conversation = messy();
+        conversation.push(message(
+            Role::User,
+            vec![ContentType::ToolResult(ToolResult::new("toolu_02", "20°C"))],
+        ));
+        // Merged into the previous user turn, which answers the assistant's only tool use.
+        assert_eq!(
+            conversation.normalize_and_validate(false).unwrap_err(),
+            BuildError::UnmatchedToolResult("toolu_02".to_string())
+        );
+
+        let assistant_first = Conversation::from(vec![message(
+            Role::Assistant,
+            vec![ContentType::text("Hello!")],
+        )]);
+        assert_eq!(
+            assistant_first.normalize_and_validate(false).unwrap_err(),
+            BuildError::FirstMessageNotUser
+        );
+
+        let unanswered = Conversation::from(vec![
+            message(Role::User, vec![ContentType::text("What's the weather?")]),
+            message(
+                Role::Assistant,
+                vec![ContentType::ToolUse(ToolUse::new(
+                    "toolu_01",
+                    "get_weather",
+                    json!({"location": "Paris"}),
+                ))],
+            ),
+            message(Role::User, vec![ContentType::text("Never mind.")]),
+        ]);
+        assert_eq!(
+            unanswered.normalize_and_validate(false).unwrap_err(),
+            BuildError::MissingToolResult("toolu_01".to_string())
+        );
+
+        let empty = Conversation::from(vec![message(Role::User, vec![])]);
+        assert_eq!(
+            empty.clone().normalize_and_validate(true).unwrap_err(),
+            BuildError::EmptyMessage(0)
+        );
+        assert_eq!(
+            empty.normalize_and_validate(false).unwrap_err(),
+            BuildError::NoMessages
+        );
+    }
+}
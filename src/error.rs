@@ -0,0 +1,359 @@
+//! Errors returned by the SDK.
+
+use reqwest::StatusCode;
+use serde::Deserialize;
+use std::fmt;
+use std::path::PathBuf;
+
+/// A `Result` alias defaulting to the SDK [`Error`].
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Errors returned by the SDK.
+#[derive(Debug)]
+pub enum Error {
+    /// The HTTP request could not be sent or its body could not be read.
+    Http(reqwest::Error),
+    /// The request did not complete within the client's timeout.
+    Timeout,
+    /// The API returned an error.
+    ///
+    /// `error_type` is the API's error type, e.g. `rate_limit_error` or `overloaded_error`.
+    /// Errors sent as events in a stream carry the status of the stream itself, `200 OK`.
+    Api {
+        status: StatusCode,
+        error_type: String,
+        message: String,
+    },
+    /// A request or response body could not be (de)serialized.
+    Deserialize(serde_json::Error),
+    /// No API key was given to the [`Client`](crate::Client) and `ANTHROPIC_API_KEY` is not set.
+    MissingApiKey,
+    /// The API key cannot be sent as a header value.
+    InvalidApiKey,
+    /// The value of the named header is not a valid header value.
+    InvalidHeader(String),
+    /// The named query parameter is empty or one the API reads itself, such as `limit`.
+    InvalidQueryParam(String),
+    /// The request could not be built.
+    Builder(BuildError),
+    /// A streamed response could not be decoded.
+    Stream(String),
+    /// The response did not contain a call to the named tool.
+    MissingToolUse(String),
+    /// The input the model gave the named tool could not be parsed.
+    InvalidToolInput {
+        name: String,
+        source: serde_json::Error,
+    },
+    /// The input of the named tool is not represented as a JSON object, which tools require.
+    UnsupportedToolSchema(String),
+    /// The model was still asking for tools after the given number of calls.
+    ToolLoopLimit(usize),
+    /// A file could not be read.
+    Io(std::io::Error),
+    /// The file or media type is not supported by the API.
+    UnsupportedMediaType(String),
+    /// The response body was larger than the client's limit, in bytes.
+    ResponseTooLarge { limit: usize },
+    /// The response was not JSON, e.g. an HTML error page from a proxy.
+    ///
+    /// `got` is the response's `Content-Type` and `body_snippet` the start of its body.
+    UnexpectedContentType { got: String, body_snippet: String },
+    /// The response contains more than one tool use with this id, so results can't be matched.
+    DuplicateToolUseId(String),
+}
+
+/// Represents the error body returned by the API, `{"type":"error","error":{...}}`.
+#[derive(Debug, Deserialize)]
+pub(crate) struct ApiErrorBody {
+    pub error: ApiErrorDetail,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ApiErrorDetail {
+    #[serde(rename = "type")]
+    pub error_type: String,
+    pub message: String,
+}
+
+impl Error {
+    /// Creates an [`Error::Api`] from a failed response, parsing the structured body when possible.
+    pub(crate) fn from_response(status: StatusCode, body: &str) -> Self {
+        match serde_json::from_str::<ApiErrorBody>(body) {
+            Ok(ApiErrorBody { error }) => Error::Api {
+                status,
+                error_type: error.error_type,
+                message: error.message,
+            },
+            Err(_) => Error::Api {
+                status,
+                error_type: "unknown".to_string(),
+                message: body.to_string(),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Http(e) => write!(f, "HTTP request failed: {}", e),
+            Error::Timeout => write!(f, "Request timed out"),
+            Error::Api {
+                status,
+                error_type,
+                message,
+            } => write!(
+                f,
+                "API request failed with status: {}. Error: {}: {}",
+                status, error_type, message
+            ),
+            Error::Deserialize(e) => write!(f, "Failed to (de)serialize JSON: {}", e),
+            Error::MissingApiKey => {
+                write!(f, "No API key provided and ANTHROPIC_API_KEY is not set")
+            }
+            Error::InvalidApiKey => write!(f, "API key is not a valid header value"),
+            Error::InvalidHeader(name) => write!(f, "Invalid value for header `{}`", name),
+            Error::InvalidQueryParam(name) => write!(f, "Invalid query parameter `{}`", name),
+            Error::Builder(e) => write!(f, "Failed to build request: {}", e),
+            Error::Stream(message) => write!(f, "Failed to decode stream: {}", message),
+            Error::MissingToolUse(name) => {
+                write!(f, "Response does not contain a call to tool `{}`", name)
+            }
+            Error::InvalidToolInput { name, source } => {
+                write!(
+                    f,
+                    "Input of tool `{}` could not be parsed: {}",
+                    name, source
+                )
+            }
+            Error::UnsupportedToolSchema(name) => {
+                write!(f, "Input of tool `{}` must be a JSON object", name)
+            }
+            Error::ToolLoopLimit(max_iterations) => write!(
+                f,
+                "Model still requested tools after {} calls",
+                max_iterations
+            ),
+            Error::Io(e) => write!(f, "Failed to read file: {}", e),
+            Error::UnsupportedMediaType(media_type) => {
+                write!(f, "Unsupported media type: {}", media_type)
+            }
+            Error::ResponseTooLarge { limit } => {
+                write!(f, "Response body exceeded the limit of {} bytes", limit)
+            }
+            Error::UnexpectedContentType { got, body_snippet } => write!(
+                f,
+                "Expected a JSON response but got `{}`: {}",
+                got, body_snippet
+            ),
+            Error::DuplicateToolUseId(id) => {
+                write!(
+                    f,
+                    "Response contains more than one tool use with id `{}`",
+                    id
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(e) => Some(e),
+            Error::Deserialize(e) => Some(e),
+            Error::InvalidToolInput { source, .. } => Some(source),
+            Error::Builder(e) => Some(e),
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Error::Timeout
+        } else {
+            Error::Http(e)
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Deserialize(e)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(e: std::io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<BuildError> for Error {
+    fn from(e: BuildError) -> Self {
+        Error::Builder(e)
+    }
+}
+
+/// Error returned when parsing a [`Model`](crate::Model) from an unknown identifier.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseModelError(pub String);
+
+impl fmt::Display for ParseModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unknown model: {}", self.0)
+    }
+}
+
+impl std::error::Error for ParseModelError {}
+
+/// Errors returned while building a [`ClaudeRequest`](crate::ClaudeRequest).
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    MissingModel,
+    /// The model identifier given to the builder is not a known model.
+    UnknownModel(String),
+    NoMessages,
+    MissingMaxTokens,
+    InvalidTemperature(f32),
+    InvalidTopP(f32),
+    InvalidTopK(u32),
+    /// Both `temperature` and `top_p` were set; only one should be used.
+    TemperatureWithTopP,
+    /// `force_single_tool` was used with the given number of tools instead of one.
+    NotSingleTool(usize),
+    /// `tool_choice` names a tool that is not in the request's tools.
+    UnknownToolChoice(String),
+    /// A tool result does not answer a tool use in the preceding assistant turn.
+    UnmatchedToolResult(String),
+    /// A tool use in an assistant turn is not answered by the following tool results.
+    MissingToolResult(String),
+    /// The message at this index has the same role as the one before it.
+    RepeatedRole(usize),
+    /// The message at this index has no content.
+    EmptyMessage(usize),
+    /// The conversation does not start with a user turn.
+    FirstMessageNotUser,
+    /// The prefill ends with whitespace, which the API rejects.
+    PrefillTrailingWhitespace,
+    /// The stop sequence at this index is empty or only whitespace, which the API rejects.
+    InvalidStopSequence(usize),
+    /// The file at `path` could not be read, for the reason in `message`.
+    UnreadableFile {
+        path: PathBuf,
+        message: String,
+    },
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::MissingModel => write!(f, "Model must be specified"),
+            BuildError::UnknownModel(id) => write!(f, "Unknown model: {}", id),
+            BuildError::NoMessages => write!(f, "At least one message must be added"),
+            BuildError::MissingMaxTokens => write!(f, "Max tokens must be specified"),
+            BuildError::InvalidTemperature(temperature) => write!(
+                f,
+                "Temperature must be between 0.0 and 1.0, got {}",
+                temperature
+            ),
+            BuildError::InvalidTopP(top_p) => {
+                write!(f, "top_p must be between 0.0 and 1.0, got {}", top_p)
+            }
+            BuildError::InvalidTopK(top_k) => write!(f, "top_k must be at least 1, got {}", top_k),
+            BuildError::TemperatureWithTopP => write!(
+                f,
+                "Only one of temperature and top_p should be set, not both"
+            ),
+            BuildError::NotSingleTool(count) => write!(
+                f,
+                "Forcing a single tool requires exactly one tool, got {}",
+                count
+            ),
+            BuildError::UnknownToolChoice(name) => {
+                write!(f, "Tool choice `{}` does not match any tool", name)
+            }
+            BuildError::UnmatchedToolResult(id) => write!(
+                f,
+                "Tool result `{}` does not match a tool use in the preceding assistant turn",
+                id
+            ),
+            BuildError::MissingToolResult(id) => {
+                write!(f, "Tool use `{}` has no matching tool result", id)
+            }
+            BuildError::RepeatedRole(index) => write!(
+                f,
+                "Message {} has the same role as the message before it",
+                index
+            ),
+            BuildError::EmptyMessage(index) => write!(f, "Message {} has no content", index),
+            BuildError::FirstMessageNotUser => {
+                write!(f, "The first message must be from the user")
+            }
+            BuildError::PrefillTrailingWhitespace => {
+                write!(f, "The prefill must not end with whitespace")
+            }
+            BuildError::InvalidStopSequence(index) => write!(
+                f,
+                "Stop sequence {} must contain a non-whitespace character",
+                index
+            ),
+            BuildError::UnreadableFile { path, message } => {
+                write!(f, "Failed to read `{}`: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+impl From<ParseModelError> for BuildError {
+    fn from(e: ParseModelError) -> Self {
+        BuildError::UnknownModel(e.0)
+    }
+}
+
+impl From<std::convert::Infallible> for BuildError {
+    fn from(e: std::convert::Infallible) -> Self {
+        match e {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rate_limit_error() {
+        let body = r#"{
+            "type": "error",
+            "error": {
+                "type": "rate_limit_error",
+                "message": "Number of request tokens has exceeded your per-minute rate limit"
+            }
+        }"#;
+
+        let error = Error::from_response(StatusCode::TOO_MANY_REQUESTS, body);
+
+        assert!(matches!(
+            error,
+            Error::Api { status, ref error_type, .. }
+                if status == StatusCode::TOO_MANY_REQUESTS && error_type == "rate_limit_error"
+        ));
+    }
+
+    #[test]
+    fn test_unstructured_error_body_is_kept() {
+        let error = Error::from_response(StatusCode::BAD_GATEWAY, "Bad Gateway");
+
+        assert!(matches!(
+            error,
+            Error::Api { ref error_type, ref message, .. }
+                if error_type == "unknown" && message == "Bad Gateway"
+        ));
+    }
+}
@@ -0,0 +1,334 @@
+//! One-call helpers for the most common ways of using the API.
+
+use crate::{
+    ClaudeRequest, ClaudeResponse, Client, ContentType, Model, Result, Role, Tool, ToolBuilder,
+    ToolChoice,
+};
+use futures::StreamExt;
+use serde::de::DeserializeOwned;
+
+/// Max tokens used by the helpers, which do not expose the full builder.
+pub(crate) const DEFAULT_MAX_TOKENS: u32 = 1024;
+
+impl Client {
+    /// Asks a single question and returns the text of the answer.
+    pub async fn ask(&self, model: Model, question: impl Into<String>) -> Result<String> {
+        let request = ClaudeRequest::builder()
+            .model(model)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: question.into(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(DEFAULT_MAX_TOKENS)
+            .build()?;
+
+        let response = self.call(&request).await?;
+
+        Ok(response.text())
+    }
+
+    /// Extracts a `T` from `text` by forcing the model to call the tool generated from `T`.
+    pub async fn extract<T>(&self, model: Model, text: impl Into<String>) -> Result<T>
+    where
+        T: ToolBuilder + DeserializeOwned,
+    {
+        self.extract_with::<T>(model, None, text.into()).await
+    }
+
+    /// Like [`extract`](Self::extract), with `instructions` sent as the system prompt.
+    pub async fn analyze<T>(
+        &self,
+        model: Model,
+        instructions: impl Into<String>,
+        text: impl Into<String>,
+    ) -> Result<T>
+    where
+        T: ToolBuilder + DeserializeOwned,
+    {
+        self.extract_with::<T>(model, Some(instructions.into()), text.into())
+            .await
+    }
+
+    /// Runs [`extract`](Self::extract) over each input, at most `concurrency` at a time.
+    ///
+    /// Results are returned in the order of `inputs`, and a failed extraction only fails its
+    /// own entry. `progress`, when given, is called with the number of completed inputs and
+    /// the total after each one finishes.
+    pub async fn extract_many<T>(
+        &self,
+        model: Model,
+        inputs: Vec<String>,
+        concurrency: usize,
+        progress: Option<&(dyn Fn(usize, usize) + Sync)>,
+    ) -> Vec<Result<T>>
+    where
+        T: ToolBuilder + DeserializeOwned,
+    {
+        let total = inputs.len();
+        let mut results: Vec<Option<Result<T>>> = (0..total).map(|_| None).collect();
+
+        let mut extractions = futures::stream::iter(inputs.into_iter().enumerate())
+            .map(|(index, input)| {
+                let model = model.clone();
+                async move { (index, self.extract::<T>(model, input).await) }
+            })
+            .buffer_unordered(concurrency.max(1));
+
+        let mut completed = 0;
+        while let Some((index, result)) = extractions.next().await {
+            results[index] = Some(result);
+            completed += 1;
+            if let Some(progress) = progress {
+                progress(completed, total);
+            }
+        }
+
+        results.into_iter().flatten().collect()
+    }
+
+    /// Calls the API with each request, at most `concurrency` at a time.
+    ///
+    /// Results are returned in the order of `requests`, and a failed call only fails its own
+    /// entry.
+    pub async fn map_requests(
+        &self,
+        requests: Vec<ClaudeRequest>,
+        concurrency: usize,
+    ) -> Vec<Result<ClaudeResponse>> {
+        let mut results: Vec<Option<Result<ClaudeResponse>>> =
+            (0..requests.len()).map(|_| None).collect();
+
+        let mut calls = futures::stream::iter(requests.iter().enumerate())
+            .map(|(index, request)| async move { (index, self.call(request).await) })
+            .buffer_unordered(concurrency.max(1));
+
+        while let Some((index, result)) = calls.next().await {
+            results[index] = Some(result);
+        }
+
+        results.into_iter().flatten().collect()
+    }
+
+    async fn extract_with<T>(
+        &self,
+        model: Model,
+        instructions: Option<String>,
+        text: String,
+    ) -> Result<T>
+    where
+        T: ToolBuilder + DeserializeOwned,
+    {
+        let mut builder = ClaudeRequest::builder()
+            .model(model)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text,
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(DEFAULT_MAX_TOKENS)
+            .tools(vec![Tool::new::<T>()])
+            .tool_choice(ToolChoice::Specific {
+                name: T::name().to_string(),
+                disable_parallel_tool_use: None,
+            });
+        if let Some(instructions) = instructions {
+            builder = builder.system(instructions);
+        }
+
+        let request = builder.build()?;
+        let response = self.call(&request).await?;
+
+        match response.extract::<T>() {
+            #[cfg(feature = "repair")]
+            Err(crate::Error::InvalidToolInput { source, .. }) => {
+                self.repair_input(request, response, T::name(), source)
+                    .await
+            }
+            result => result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::message_json;
+    use futures::future::BoxFuture;
+    use reqwest::StatusCode;
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+    use wiremock::matchers::{body_partial_json, body_string_contains, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+    struct SuperBowl {
+        year: u16,
+        winner: String,
+        loser: String,
+    }
+
+    impl ToolBuilder for SuperBowl {
+        fn name() -> &'static str {
+            "extract_super_bowl_info"
+        }
+
+        fn description() -> Option<&'static str> {
+            Some("Extract Super Bowl information from text")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_extract_forces_the_tool_and_parses_its_input() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v1/messages"))
+            .and(body_partial_json(json!({
+                "tool_choice": {"type": "tool", "name": "extract_super_bowl_info"}
+            })))
+            .respond_with(ResponseTemplate::new(200).set_body_json(message_json(
+                json!([{
+                    "type": "tool_use",
+                    "id": "toolu_01",
+                    "name": "extract_super_bowl_info",
+                    "input": {"year": 1982, "winner": "Green Bay Packers", "loser": "Miami Dolphins"}
+                }]),
+                "tool_use",
+            )))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let super_bowl: SuperBowl = client
+            .extract(
+                Model::Haiku3,
+                "The Green Bay Packers beat the Miami Dolphins in the 1982 Super Bowl 31-10.",
+            )
+            .await?;
+
+        assert_eq!(
+            super_bowl,
+            SuperBowl {
+                year: 1982,
+                winner: "Green Bay Packers".to_string(),
+                loser: "Miami Dolphins".to_string(),
+            }
+        );
+
+        Ok(())
+    }
+
+    fn tool_use_response(input: serde_json::Value) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(message_json(
+            json!([{
+                "type": "tool_use",
+                "id": "toolu_01",
+                "name": "extract_super_bowl_info",
+                "input": input
+            }]),
+            "tool_use",
+        ))
+    }
+
+    /// Echoes each request's text back after a pause, tracking how many are in flight.
+    #[derive(Debug, Default)]
+    struct EchoTransport {
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    impl crate::Transport for EchoTransport {
+        fn send(&self, request: reqwest::Request) -> BoxFuture<'_, Result<(StatusCode, String)>> {
+            let body: serde_json::Value =
+                serde_json::from_slice(request.body().and_then(|body| body.as_bytes()).unwrap())
+                    .unwrap();
+            let text = body["messages"][0]["content"][0]["text"].clone();
+
+            Box::pin(async move {
+                let in_flight = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                self.max_in_flight.fetch_max(in_flight, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                self.in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                let response = message_json(json!([{"type": "text", "text": text}]), "end_turn");
+                Ok((StatusCode::OK, response.to_string()))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_map_requests_bounds_concurrency() {
+        let transport = Arc::new(EchoTransport::default());
+        let client = Client::with_api_key("test-key").with_transport(transport.clone());
+        let requests: Vec<ClaudeRequest> = (0..8)
+            .map(|i| {
+                ClaudeRequest::builder()
+                    .model(Model::Haiku3)
+                    .user(format!("Document {}", i))
+                    .max_tokens(10)
+                    .build()
+                    .expect("Failed to build request")
+            })
+            .collect();
+
+        let responses = client.map_requests(requests, 3).await;
+
+        let texts: Vec<String> = responses
+            .into_iter()
+            .map(|response| response.expect("Call failed").text())
+            .collect();
+        let expected: Vec<String> = (0..8).map(|i| format!("Document {}", i)).collect();
+        assert_eq!(texts, expected);
+        assert_eq!(transport.max_in_flight.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_extract_many_keeps_input_order() {
+        let server = MockServer::start().await;
+        for (year, winner, loser) in [
+            (1967, "Green Bay Packers", "Kansas City Chiefs"),
+            (1968, "Green Bay Packers", "Oakland Raiders"),
+            (1969, "New York Jets", "Baltimore Colts"),
+        ] {
+            Mock::given(method("POST"))
+                .and(body_string_contains(year.to_string()))
+                .respond_with(tool_use_response(
+                    json!({"year": year, "winner": winner, "loser": loser}),
+                ))
+                .mount(&server)
+                .await;
+        }
+
+        let inputs = vec![
+            "Super Bowl I was played in 1967.".to_string(),
+            "Super Bowl II was played in 1968.".to_string(),
+            "Super Bowl III was played in 1969.".to_string(),
+        ];
+        let calls = AtomicUsize::new(0);
+        let progress = |completed: usize, total: usize| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            assert!(completed <= total);
+        };
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let results = client
+            .extract_many::<SuperBowl>(Model::Haiku3, inputs, 2, Some(&progress))
+            .await;
+
+        let years: Vec<u16> = results
+            .into_iter()
+            .map(|result| result.expect("Extraction failed").year)
+            .collect();
+        assert_eq!(years, vec![1967, 1968, 1969]);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+}
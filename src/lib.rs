@@ -2,25 +2,255 @@
 //!
 //! This SDK provides a way to interact with the Claude API using a simple builder pattern.
 
-use anyhow::{Context, Result};
-use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+use schemars::gen::SchemaSettings;
+use schemars::schema::Schema;
 use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use serde_json::Value;
-use std::collections::HashMap;
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+
+#[cfg(test)]
+#[macro_use]
+mod test_util;
+
+mod agent;
+mod batch;
+mod client;
+mod clock;
+mod conversation;
+mod error;
+mod helpers;
+mod lint;
+mod media;
+mod rate_limit;
+#[cfg(feature = "repair")]
+mod repair;
+mod retry;
+mod schema;
+mod stream;
+mod template;
+#[cfg(feature = "tracing")]
+mod trace;
+mod transport;
+
+pub use agent::{Agent, ToolHandler, ToolHandlerError, ToolRun};
+pub use batch::{BatchOutcome, BatchRequestCounts, BatchResult, BatchStatus, MessageBatch};
+pub use client::{CallOptions, Client, ClientBuilder, ResponseEnvelope};
+#[cfg(feature = "testing")]
+pub use clock::MockClock;
+pub use clock::{Clock, TokioClock};
+pub use conversation::Conversation;
+pub use error::{BuildError, Error, ParseModelError, Result};
+pub use lint::Lint;
+pub use rate_limit::{LimitStatus, RateLimit, RateLimitInfo, RateLimiter};
+#[cfg(feature = "repair")]
+pub use repair::repair_json;
+pub use retry::RetryPolicy;
+pub use stream::{DeltaUsage, StreamEvent, TextStreamExt};
+pub use template::RequestTemplate;
+#[cfg(feature = "testing")]
+pub use transport::MockTransport;
+pub use transport::Transport;
+
+/// Beta flag enabling prompt caching.
+const PROMPT_CACHING_BETA: &str = "prompt-caching-2024-07-31";
+const EXTENDED_CACHE_TTL_BETA: &str = "extended-cache-ttl-2025-04-11";
 
 /// Available Claude Models.
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Model {
-    #[serde(rename = "claude-3-5-sonnet-20240620")]
     Sonnet35,
-    #[serde(rename = "claude-3-opus-20240229")]
     Opus3,
-    #[serde(rename = "claude-3-sonnet-20240229")]
     Sonnet3,
-    #[serde(rename = "claude-3-haiku-20240307")]
     Haiku3,
+    Sonnet35V2,
+    Haiku35,
+    Sonnet37,
+    Sonnet35Latest,
+    Haiku35Latest,
+    Sonnet37Latest,
+    Opus3Latest,
+    Opus4,
+    Sonnet4,
+    Opus41,
+    Sonnet45,
+    Haiku45,
+    Opus45,
+    /// Any model identifier not listed above, such as a newly released snapshot.
+    Other(String),
+}
+
+impl Model {
+    /// Every model with a known identifier.
+    const KNOWN: [Model; 17] = [
+        Model::Sonnet35,
+        Model::Opus3,
+        Model::Sonnet3,
+        Model::Haiku3,
+        Model::Sonnet35V2,
+        Model::Haiku35,
+        Model::Sonnet37,
+        Model::Sonnet35Latest,
+        Model::Haiku35Latest,
+        Model::Sonnet37Latest,
+        Model::Opus3Latest,
+        Model::Opus4,
+        Model::Sonnet4,
+        Model::Opus41,
+        Model::Sonnet45,
+        Model::Haiku45,
+        Model::Opus45,
+    ];
+
+    /// Returns the identifier the API uses for the model, e.g. `"claude-3-haiku-20240307"`.
+    pub fn id(&self) -> &str {
+        match self {
+            Model::Sonnet35 => "claude-3-5-sonnet-20240620",
+            Model::Opus3 => "claude-3-opus-20240229",
+            Model::Sonnet3 => "claude-3-sonnet-20240229",
+            Model::Haiku3 => "claude-3-haiku-20240307",
+            Model::Sonnet35V2 => "claude-3-5-sonnet-20241022",
+            Model::Haiku35 => "claude-3-5-haiku-20241022",
+            Model::Sonnet37 => "claude-3-7-sonnet-20250219",
+            Model::Sonnet35Latest => "claude-3-5-sonnet-latest",
+            Model::Haiku35Latest => "claude-3-5-haiku-latest",
+            Model::Sonnet37Latest => "claude-3-7-sonnet-latest",
+            Model::Opus3Latest => "claude-3-opus-latest",
+            Model::Opus4 => "claude-opus-4-20250514",
+            Model::Sonnet4 => "claude-sonnet-4-20250514",
+            Model::Opus41 => "claude-opus-4-1-20250805",
+            Model::Sonnet45 => "claude-sonnet-4-5-20250929",
+            Model::Haiku45 => "claude-haiku-4-5-20251001",
+            Model::Opus45 => "claude-opus-4-5-20251101",
+            Model::Other(id) => id,
+        }
+    }
+
+    /// Returns the family of the model, or `None` for [`Model::Other`].
+    ///
+    /// Tiers order by capability, so sorting by tier in reverse puts the most capable models
+    /// first, e.g. for a fallback chain. Combine with [`version`](Self::version) to prefer
+    /// newer models within a tier.
+    pub fn tier(&self) -> Option<ModelTier> {
+        match self {
+            Model::Haiku3 | Model::Haiku35 | Model::Haiku35Latest | Model::Haiku45 => {
+                Some(ModelTier::Haiku)
+            }
+            Model::Sonnet3
+            | Model::Sonnet35
+            | Model::Sonnet35V2
+            | Model::Sonnet35Latest
+            | Model::Sonnet37
+            | Model::Sonnet37Latest
+            | Model::Sonnet4
+            | Model::Sonnet45 => Some(ModelTier::Sonnet),
+            Model::Opus3 | Model::Opus3Latest | Model::Opus4 | Model::Opus41 | Model::Opus45 => {
+                Some(ModelTier::Opus)
+            }
+            Model::Other(_) => None,
+        }
+    }
+
+    /// Returns the major and minor version of the model, e.g. `(3, 5)` for Claude 3.5 Sonnet,
+    /// or `None` for [`Model::Other`].
+    ///
+    /// Snapshots of the same version, such as [`Model::Sonnet35`] and [`Model::Sonnet35V2`],
+    /// and their `-latest` aliases share a version.
+    pub fn version(&self) -> Option<(u32, u32)> {
+        match self {
+            Model::Opus3 | Model::Opus3Latest | Model::Sonnet3 | Model::Haiku3 => Some((3, 0)),
+            Model::Sonnet35
+            | Model::Sonnet35V2
+            | Model::Sonnet35Latest
+            | Model::Haiku35
+            | Model::Haiku35Latest => Some((3, 5)),
+            Model::Sonnet37 | Model::Sonnet37Latest => Some((3, 7)),
+            Model::Opus4 | Model::Sonnet4 => Some((4, 0)),
+            Model::Opus41 => Some((4, 1)),
+            Model::Sonnet45 | Model::Haiku45 | Model::Opus45 => Some((4, 5)),
+            Model::Other(_) => None,
+        }
+    }
+
+    /// Returns the identifier the API uses for the model; the same as [`id`](Self::id).
+    ///
+    /// Known identifiers parse back into the same model with [`FromStr`].
+    pub fn as_str(&self) -> &str {
+        self.id()
+    }
+}
+
+/// The family of a [`Model`], ordered by capability: `Haiku < Sonnet < Opus`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ModelTier {
+    /// The fastest and cheapest models.
+    Haiku,
+    /// Balanced speed and capability.
+    Sonnet,
+    /// The most capable models.
+    Opus,
+}
+
+impl std::fmt::Display for Model {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.id())
+    }
+}
+
+impl Serialize for Model {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_str(self.id())
+    }
+}
+
+impl<'de> Deserialize<'de> for Model {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        let id = String::deserialize(deserializer)?;
+        Ok(id.parse().unwrap_or(Model::Other(id)))
+    }
+}
+
+impl FromStr for Model {
+    type Err = ParseModelError;
+
+    /// Parses a model from its API identifier, e.g. `"claude-3-5-sonnet-latest"`.
+    ///
+    /// Only known identifiers are accepted; use [`Model::Other`] to name any other model.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Model::KNOWN
+            .iter()
+            .find(|model| model.id() == s)
+            .cloned()
+            .ok_or_else(|| ParseModelError(s.to_string()))
+    }
+}
+
+impl TryFrom<&str> for Model {
+    type Error = ParseModelError;
+
+    /// Parses a model from its API identifier, as [`FromStr`] does.
+    fn try_from(id: &str) -> Result<Self, Self::Error> {
+        id.parse()
+    }
+}
+
+impl TryFrom<String> for Model {
+    type Error = ParseModelError;
+
+    /// Parses a model from its API identifier, as [`FromStr`] does.
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        id.parse()
+    }
 }
 
 /// Represents the role of a message in a conversation.
@@ -32,26 +262,236 @@ pub enum Role {
 }
 
 /// Represents the source of an image in a message.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ImageSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+/// Represents the source of a document in a message.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DocumentSource {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+}
+
+/// Marks a content block as a prompt-caching breakpoint.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum CacheControl {
+    /// Cached for `ttl`, or five minutes when not set.
+    Ephemeral {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        ttl: Option<CacheTtl>,
+    },
+}
+
+/// How long a cached prompt is kept; the only lifetimes the API accepts.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum CacheTtl {
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    /// Requires the extended cache TTL beta, which requests using it send automatically.
+    #[serde(rename = "1h")]
+    OneHour,
+}
+
+/// Represents the system prompt, either as plain text or as content blocks.
+///
+/// Blocks allow a long system prompt to be cached with [`ContentType::cached`].
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ImageSource {
-    #[serde(rename = "type")]
-    pub source_type: String,
-    pub media_type: String,
-    pub data: String,
+#[serde(untagged)]
+pub enum System {
+    Text(String),
+    Blocks(Vec<ContentType>),
+}
+
+impl System {
+    /// Returns the content blocks of the prompt; plain text has none.
+    pub fn blocks(&self) -> &[ContentType] {
+        match self {
+            System::Text(_) => &[],
+            System::Blocks(blocks) => blocks,
+        }
+    }
+}
+
+/// Deserializes a request's `system` field from a string or an array of content blocks.
+///
+/// Blocks are checked as [`ContentType::try_from`] does, so a malformed block is an error
+/// rather than an unknown one.
+fn deserialize_system<'de, D>(deserializer: D) -> Result<Option<System>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::de::Error as _;
+
+    match Option::<Value>::deserialize(deserializer)? {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(text)) => Ok(Some(System::Text(text))),
+        Some(Value::Array(blocks)) => blocks
+            .into_iter()
+            .map(ContentType::try_from)
+            .collect::<Result<Vec<_>>>()
+            .map(|blocks| Some(System::Blocks(blocks)))
+            .map_err(D::Error::custom),
+        Some(_) => Err(D::Error::custom(
+            "system must be a string or an array of content blocks",
+        )),
+    }
+}
+
+impl From<String> for System {
+    fn from(text: String) -> Self {
+        System::Text(text)
+    }
+}
+
+impl From<&str> for System {
+    fn from(text: &str) -> Self {
+        System::Text(text.to_string())
+    }
+}
+
+impl From<Vec<ContentType>> for System {
+    fn from(blocks: Vec<ContentType>) -> Self {
+        System::Blocks(blocks)
+    }
 }
 
 /// Represents the type of content in a message.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type")]
 pub enum ContentType {
     #[serde(rename = "text")]
-    Text { text: String },
+    Text {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
     #[serde(rename = "image")]
     Image { source: ImageSource },
+    #[serde(rename = "document")]
+    Document {
+        source: DocumentSource,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<CacheControl>,
+    },
+    /// The model's reasoning before its answer, when extended thinking is enabled.
+    ///
+    /// The signature must be sent back unchanged with the block, e.g. when continuing after a
+    /// tool use. It is empty at the start of a streamed block and arrives in a later delta.
+    #[serde(rename = "thinking")]
+    Thinking {
+        thinking: String,
+        #[serde(default)]
+        signature: String,
+    },
     #[serde(rename = "tool_use")]
     ToolUse(ToolUse),
     #[serde(rename = "tool_result")]
     ToolResult(ToolResult),
+    /// A call to a server tool, such as web search, which the API runs itself.
+    ///
+    /// Unlike [`ContentType::ToolUse`], these need no tool result from the caller.
+    #[serde(rename = "server_tool_use")]
+    ServerToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    /// Results of the web search server tool, kept as sent so they can be passed back.
+    #[serde(rename = "web_search_tool_result")]
+    WebSearchToolResult { tool_use_id: String, content: Value },
+    /// A block this crate does not know about, kept as sent.
+    ///
+    /// New block types are added to the API over time; keeping them lets older versions of
+    /// this crate still parse the rest of the response and send the block back unchanged.
+    #[serde(untagged)]
+    Unknown(Value),
+}
+
+impl ContentType {
+    /// Creates a text block.
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentType::Text {
+            text: text.into(),
+            cache_control: None,
+        }
+    }
+
+    /// Marks the block as cacheable, so the prompt up to and including it can be reused.
+    ///
+    /// Only text and document blocks can be cached; other blocks are returned unchanged.
+    pub fn cached(self) -> Self {
+        self.set_cache_control(CacheControl::Ephemeral { ttl: None })
+    }
+
+    /// Like [`cached`](Self::cached), keeping the cached prompt for `ttl`.
+    pub fn cached_for(self, ttl: CacheTtl) -> Self {
+        self.set_cache_control(CacheControl::Ephemeral { ttl: Some(ttl) })
+    }
+
+    fn set_cache_control(mut self, control: CacheControl) -> Self {
+        match &mut self {
+            ContentType::Text { cache_control, .. }
+            | ContentType::Document { cache_control, .. } => {
+                *cache_control = Some(control);
+            }
+            _ => {}
+        }
+        self
+    }
+
+    /// Returns how the block is cached, if it is marked as cacheable.
+    pub(crate) fn cache_control(&self) -> Option<&CacheControl> {
+        match self {
+            ContentType::Text { cache_control, .. }
+            | ContentType::Document { cache_control, .. } => cache_control.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// The `type` of every block [`ContentType`] has a variant for.
+const KNOWN_BLOCK_TYPES: [&str; 8] = [
+    "text",
+    "image",
+    "document",
+    "thinking",
+    "tool_use",
+    "tool_result",
+    "server_tool_use",
+    "web_search_tool_result",
+];
+
+/// Creates a [`Error::Deserialize`] with the given message.
+fn invalid_json(message: impl std::fmt::Display) -> Error {
+    Error::Deserialize(serde::de::Error::custom(message))
+}
+
+impl TryFrom<Value> for ContentType {
+    type Error = Error;
+
+    /// Converts a JSON content block such as `{"type": "text", "text": "Hello"}`.
+    ///
+    /// Blocks of a type this crate does not know become [`ContentType::Unknown`], but a block
+    /// of a known type with missing or invalid fields is an error.
+    fn try_from(value: Value) -> Result<Self> {
+        let block_type = match value.get("type") {
+            Some(Value::String(block_type)) => block_type.clone(),
+            _ => return Err(invalid_json("content block has no string `type`")),
+        };
+
+        match serde_json::from_value(value)? {
+            ContentType::Unknown(_) if KNOWN_BLOCK_TYPES.contains(&block_type.as_str()) => Err(
+                invalid_json(format!("`{}` content block is invalid", block_type)),
+            ),
+            content => Ok(content),
+        }
+    }
 }
 
 /// Represents a message in a conversation.
@@ -61,6 +501,36 @@ pub struct Message {
     pub content: Vec<ContentType>,
 }
 
+impl TryFrom<Value> for Message {
+    type Error = Error;
+
+    /// Converts a JSON message, checking each content block as [`ContentType::try_from`] does.
+    ///
+    /// As in the API, `content` may be a plain string, which becomes a single text block.
+    fn try_from(value: Value) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct RawMessage {
+            role: Role,
+            content: Value,
+        }
+
+        let raw: RawMessage = serde_json::from_value(value)?;
+        let content = match raw.content {
+            Value::String(text) => vec![ContentType::text(text)],
+            Value::Array(blocks) => blocks
+                .into_iter()
+                .map(ContentType::try_from)
+                .collect::<Result<_>>()?,
+            _ => return Err(invalid_json("message content must be a string or an array")),
+        };
+
+        Ok(Message {
+            role: raw.role,
+            content,
+        })
+    }
+}
+
 /// Represents the JSON-Schema input
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct InputSchema {
@@ -68,6 +538,15 @@ pub struct InputSchema {
     schema_type: String,
     properties: Value,
     required: Vec<String>,
+    #[serde(
+        rename = "additionalProperties",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    additional_properties: Option<bool>,
+    /// Schemas of nested types, referenced from `properties` as `#/$defs/<name>`.
+    #[serde(rename = "$defs", default, skip_serializing_if = "Map::is_empty")]
+    definitions: Map<String, Value>,
 }
 
 /// Represents a tool that can be used by the model.
@@ -86,46 +565,249 @@ pub trait ToolBuilder: JsonSchema {
 
 impl Tool {
     /// Creates a new Tool with a struct-based input schema.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `T` is not represented as a JSON object; see [`try_new`](Self::try_new).
     pub fn new<T: ToolBuilder>() -> Self {
-        let schema = schemars::schema_for!(T);
-        let schema = schema.schema.object.unwrap();
+        Self::try_new::<T>().expect("Failed to create tool")
+    }
+
+    /// Creates a new Tool with a struct-based input schema, failing if `T` is not represented
+    /// as a JSON object.
+    ///
+    /// Newtypes around a struct, such as `struct Input(Inner)`, use the schema of the inner
+    /// struct. Tool inputs must be objects, so newtypes around scalars and enums are
+    /// rejected with [`Error::UnsupportedToolSchema`].
+    ///
+    /// When [`ToolBuilder::description`] returns `None`, the doc comment on `T` is used as the
+    /// description, if it has one. The schema does not allow properties beyond those of `T`.
+    pub fn try_new<T: ToolBuilder>() -> Result<Self> {
+        let mut settings = SchemaSettings::default();
+        settings.definitions_path = "#/$defs/".to_string();
+        let root = settings.into_generator().into_root_schema_for::<T>();
+
+        // Newtypes refer to the schema of the type they wrap, directly or through `allOf`.
+        let mut schema = root.schema;
+        let reference = match schema
+            .subschemas
+            .as_deref()
+            .and_then(|subschemas| subschemas.all_of.as_deref())
+        {
+            Some([Schema::Object(inner)]) => inner.reference.as_deref(),
+            _ => schema.reference.as_deref(),
+        };
+        let wrapped = reference
+            .and_then(|reference| reference.strip_prefix("#/$defs/"))
+            .and_then(|name| root.definitions.get(name));
+        let mut doc = schema
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.description.clone());
+        if let Some(Schema::Object(inner)) = wrapped {
+            schema = inner.clone();
+            doc = doc.or_else(|| {
+                schema
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.description.clone())
+            });
+        }
+
+        let Some(object) = schema.object else {
+            return Err(Error::UnsupportedToolSchema(T::name().to_string()));
+        };
 
-        let properties = serde_json::to_value(schema.properties).unwrap();
-        let required = schema.required.into_iter().collect();
+        let properties = serde_json::to_value(object.properties)?;
+        let required = object.required.into_iter().collect();
+        let definitions = root
+            .definitions
+            .into_iter()
+            .map(|(name, schema)| Ok((name, serde_json::to_value(schema)?)))
+            .collect::<Result<_>>()?;
 
-        Tool {
+        Ok(Tool {
             name: T::name().to_string(),
-            description: T::description().map(|s| s.to_string()),
+            description: T::description().map(|s| s.to_string()).or(doc),
             input_schema: InputSchema {
                 schema_type: "object".to_string(),
                 properties,
                 required,
+                additional_properties: Some(false),
+                definitions,
             },
-        }
+        })
+    }
+
+    /// Checks that `sample` conforms to this tool's input schema and parses it into `T`.
+    ///
+    /// Types, enums, numeric and length bounds, required and unknown properties are checked
+    /// throughout the sample, including nested types in `$defs`. Meant for unit tests of tool
+    /// definitions; fails with [`Error::InvalidToolInput`] like [`ToolUse::parse_input`].
+    pub fn check_sample<T: DeserializeOwned>(&self, sample: Value) -> Result<T> {
+        let invalid = |source: serde_json::Error| Error::InvalidToolInput {
+            name: self.name.clone(),
+            source,
+        };
+
+        let schema = serde_json::to_value(&self.input_schema)?;
+        schema::validate(&sample, &schema, &self.input_schema.definitions)
+            .map_err(|message| invalid(serde::de::Error::custom(message)))?;
+
+        serde_json::from_value(sample).map_err(invalid)
     }
 }
 
+/// Creates a `Vec<Tool>` from types implementing [`ToolBuilder`].
+///
+/// `tools![A, B]` is shorthand for `vec![Tool::new::<A>(), Tool::new::<B>()]`.
+#[macro_export]
+macro_rules! tools {
+    ($($tool:ty),* $(,)?) => {
+        vec![$($crate::Tool::new::<$tool>()),*]
+    };
+}
+
 /// Represents the model's use of a tool.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ToolUse {
-    #[serde(rename = "type")]
+    // The `type` tag is written by `ContentType`, so it is only filled in on the way in.
+    #[serde(rename = "type", skip_serializing, default = "tool_use_type")]
     pub tool_type: String,
     pub id: String,
     pub name: String,
     pub input: Value,
 }
 
-/// Represents the result of a tool execution.
+/// Represents a [`ToolUse`] whose input has been parsed into `T`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypedToolUse<T> {
+    pub id: String,
+    pub name: String,
+    pub input: T,
+}
+
+impl ToolUse {
+    /// Creates a new ToolUse calling the tool `name` with `input`.
+    pub fn new(id: impl Into<String>, name: impl Into<String>, input: Value) -> Self {
+        ToolUse {
+            tool_type: tool_use_type(),
+            id: id.into(),
+            name: name.into(),
+            input,
+        }
+    }
+
+    /// Parses the input into `T`.
+    ///
+    /// Fails with [`Error::InvalidToolInput`], naming the tool, if the input does not match.
+    pub fn parse_input<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_value(self.input.clone()).map_err(|source| Error::InvalidToolInput {
+            name: self.name.clone(),
+            source,
+        })
+    }
+
+    /// Parses the input into `T`, keeping the id and name alongside it.
+    pub fn typed<T: DeserializeOwned>(&self) -> Result<TypedToolUse<T>> {
+        Ok(TypedToolUse {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            input: self.parse_input()?,
+        })
+    }
+}
+
+fn tool_use_type() -> String {
+    "tool_use".to_string()
+}
+
+/// Represents the result of a tool execution.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ToolResult {
-    #[serde(rename = "type")]
+    // The `type` tag is written by `ContentType`, so it is only filled in on the way in.
+    #[serde(rename = "type", skip_serializing, default = "tool_result_type")]
     pub result_type: String,
     pub tool_use_id: String,
-    pub content: String,
+    pub content: ToolResultContent,
+    /// Marks the result as a failed execution of the tool.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_error: bool,
+}
+
+impl ToolResult {
+    /// Creates a new ToolResult answering the tool use with id `tool_use_id`.
+    pub fn new(tool_use_id: impl Into<String>, content: impl Into<ToolResultContent>) -> Self {
+        ToolResult {
+            result_type: tool_result_type(),
+            tool_use_id: tool_use_id.into(),
+            content: content.into(),
+            is_error: false,
+        }
+    }
+
+    /// Creates a new ToolResult reporting that the tool use with id `tool_use_id` failed.
+    pub fn error(tool_use_id: impl Into<String>, content: impl Into<ToolResultContent>) -> Self {
+        ToolResult {
+            is_error: true,
+            ..ToolResult::new(tool_use_id, content)
+        }
+    }
+}
+
+/// Represents the output of a tool, either as plain text or as content blocks.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum ToolResultContent {
+    Text(String),
+    Blocks(Vec<ContentType>),
+}
+
+impl ToolResultContent {
+    /// Returns the output if it is plain text.
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            ToolResultContent::Text(text) => Some(text),
+            ToolResultContent::Blocks(_) => None,
+        }
+    }
+}
+
+impl From<String> for ToolResultContent {
+    fn from(text: String) -> Self {
+        ToolResultContent::Text(text)
+    }
+}
+
+impl From<&str> for ToolResultContent {
+    fn from(text: &str) -> Self {
+        ToolResultContent::Text(text.to_string())
+    }
+}
+
+impl From<Vec<ContentType>> for ToolResultContent {
+    fn from(blocks: Vec<ContentType>) -> Self {
+        ToolResultContent::Blocks(blocks)
+    }
+}
+
+fn tool_result_type() -> String {
+    "tool_result".to_string()
+}
+
+/// Represents whether the model thinks before answering.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Thinking {
+    /// Extended thinking, using up to `budget_tokens` of the response's `max_tokens`.
+    Enabled {
+        budget_tokens: u32,
+    },
+    Disabled,
 }
 
 /// Represents how the model should use the provided tools.
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ToolChoice {
     None,
     Auto {
@@ -189,23 +871,90 @@ impl Serialize for ToolChoice {
     }
 }
 
+impl<'de> Deserialize<'de> for ToolChoice {
+    /// Reads the shape written by `Serialize`, where `{}` is [`ToolChoice::None`].
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Wire {
+            #[serde(rename = "type")]
+            choice_type: Option<String>,
+            name: Option<String>,
+            disable_parallel_tool_use: Option<bool>,
+        }
+
+        let wire = Wire::deserialize(deserializer)?;
+        let disable_parallel_tool_use = wire.disable_parallel_tool_use;
+        match wire.choice_type.as_deref() {
+            None | Some("none") => Ok(ToolChoice::None),
+            Some("auto") => Ok(ToolChoice::Auto {
+                disable_parallel_tool_use,
+            }),
+            Some("any") => Ok(ToolChoice::Any {
+                disable_parallel_tool_use,
+            }),
+            Some("tool") => Ok(ToolChoice::Specific {
+                name: wire
+                    .name
+                    .ok_or_else(|| serde::de::Error::missing_field("name"))?,
+                disable_parallel_tool_use,
+            }),
+            Some(other) => Err(serde::de::Error::unknown_variant(
+                other,
+                &["auto", "any", "tool", "none"],
+            )),
+        }
+    }
+}
+
 /// Represents the usage statistics for an API call.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Usage {
     pub input_tokens: u32,
     pub output_tokens: u32,
+    /// Input tokens written to the prompt cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u32>,
+    /// Input tokens read from the prompt cache.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
+    /// Output tokens spent on extended thinking, when reported separately.
+    ///
+    /// These are already counted in `output_tokens`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking_tokens: Option<u32>,
+}
+
+impl Usage {
+    /// Returns the input and output tokens added together.
+    ///
+    /// Cached input tokens are reported separately and are not included.
+    pub fn total_tokens(&self) -> u32 {
+        self.input_tokens + self.output_tokens
+    }
 }
 
 /// Represents the stopping reason in the API response.
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename = "snake_case")]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
 pub enum StopReason {
+    EndTurn,
     MaxTokens,
+    StopSequence,
     ToolUse,
+    /// A long-running server tool paused the turn; send the response back to continue it.
+    PauseTurn,
+    /// The model declined to respond.
+    Refusal,
+    /// Any stop reason not listed above.
+    #[serde(untagged)]
+    Other(String),
 }
 
 /// Represents the response from the Claude API.
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ClaudeResponse {
     pub id: String,
     #[serde(rename = "type")]
@@ -216,6 +965,158 @@ pub struct ClaudeResponse {
     pub stop_reason: Option<StopReason>,
     pub stop_sequence: Option<String>,
     pub usage: Usage,
+    /// Set when a streamed response ended before `message_stop`, in which case the content is
+    /// what arrived until then. Never set on responses that were not streamed.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
+    /// The response body exactly as received, kept when the client was set up with
+    /// [`Client::with_raw_response`]. Never set on streamed responses.
+    #[serde(skip)]
+    pub raw: Option<String>,
+}
+
+/// Represents a page found by the web search server tool.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct WebSearchResult {
+    pub title: String,
+    pub url: String,
+    #[serde(default)]
+    pub page_age: Option<String>,
+    /// Opaque page content, which must be sent back unchanged for the model to cite it.
+    pub encrypted_content: String,
+}
+
+/// Represents everything in a [`ClaudeResponse`] except its content.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResponseMeta {
+    pub id: String,
+    pub response_type: String,
+    pub role: Role,
+    pub model: Model,
+    pub stop_reason: Option<StopReason>,
+    pub stop_sequence: Option<String>,
+    pub usage: Usage,
+    pub incomplete: bool,
+    pub raw: Option<String>,
+}
+
+impl ClaudeResponse {
+    /// Consumes the response, returning the owned content and the remaining metadata.
+    pub fn into_parts(self) -> (Vec<ContentType>, ResponseMeta) {
+        let meta = ResponseMeta {
+            id: self.id,
+            response_type: self.response_type,
+            role: self.role,
+            model: self.model,
+            stop_reason: self.stop_reason,
+            stop_sequence: self.stop_sequence,
+            usage: self.usage,
+            incomplete: self.incomplete,
+            raw: self.raw,
+        };
+
+        (self.content, meta)
+    }
+
+    /// Returns the pages found by any web searches in the response.
+    ///
+    /// Searches that failed are skipped.
+    pub fn web_search_results(&self) -> Vec<WebSearchResult> {
+        self.content
+            .iter()
+            .filter_map(|content| match content {
+                ContentType::WebSearchToolResult {
+                    content: Value::Array(results),
+                    ..
+                } => Some(results),
+                _ => None,
+            })
+            .flatten()
+            .filter(|result| result["type"] == "web_search_result")
+            .filter_map(|result| serde_json::from_value(result.clone()).ok())
+            .collect()
+    }
+
+    /// Returns the response as an assistant message, to append to the conversation before the
+    /// next turn.
+    ///
+    /// All blocks are kept in order, including tool uses and thinking blocks, which the API
+    /// expects to be sent back unchanged.
+    pub fn assistant_message(&self) -> Message {
+        Message {
+            role: Role::Assistant,
+            content: self.content.clone(),
+        }
+    }
+
+    /// Returns the stop sequence the response ended with, if it ended on one of the request's
+    /// stop sequences.
+    pub fn matched_stop_sequence(&self) -> Option<&str> {
+        match self.stop_reason {
+            Some(StopReason::StopSequence) => self.stop_sequence.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Returns the text of all text blocks in the response, concatenated.
+    pub fn text(&self) -> String {
+        self.content
+            .iter()
+            .filter_map(|content| match content {
+                ContentType::Text { text, .. } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the calls to client tools in the response, in order.
+    ///
+    /// Server tool calls such as web searches are run by the API and are not included.
+    pub fn tool_uses(&self) -> Vec<&ToolUse> {
+        self.content
+            .iter()
+            .filter_map(|content| match content {
+                ContentType::ToolUse(tool_use) => Some(tool_use),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Returns the calls to client tools like [`tool_uses`](Self::tool_uses), checking that
+    /// their ids are unique.
+    ///
+    /// Fails with [`Error::DuplicateToolUseId`] if two calls share an id, since their results
+    /// could not be told apart.
+    pub fn unique_tool_uses(&self) -> Result<Vec<&ToolUse>> {
+        let tool_uses = self.tool_uses();
+        let mut ids = HashSet::with_capacity(tool_uses.len());
+        if let Some(duplicate) = tool_uses.iter().find(|tool_use| !ids.insert(&tool_use.id)) {
+            return Err(Error::DuplicateToolUseId(duplicate.id.clone()));
+        }
+
+        Ok(tool_uses)
+    }
+
+    /// Parses the input of the first call to the tool generated from `T`.
+    ///
+    /// Fails with [`Error::MissingToolUse`] if the model did not call the tool.
+    pub fn extract<T: ToolBuilder + DeserializeOwned>(&self) -> Result<T> {
+        self.tool_uses()
+            .into_iter()
+            .find(|tool_use| tool_use.name == T::name())
+            .ok_or_else(|| Error::MissingToolUse(T::name().to_string()))?
+            .parse_input()
+    }
+
+    /// Pairs the first tool use in the response with the tool of the same name in `tools`.
+    ///
+    /// Returns `None` if the response has no tool use or names a tool that is not in `tools`.
+    pub fn match_tool<'a>(&self, tools: &'a [Tool]) -> Option<(&'a Tool, &ToolUse)> {
+        let tool_use = *self.tool_uses().first()?;
+        let tool = tools.iter().find(|tool| tool.name == tool_use.name)?;
+
+        Some((tool, tool_use))
+    }
 }
 
 /// Builder for creating a request to the Claude API.
@@ -227,24 +1128,124 @@ pub struct ClaudeRequestBuilder {
     pub metadata: Option<HashMap<String, String>>,
     pub stop_sequences: Option<Vec<String>>,
     pub stream: Option<bool>,
-    pub system: Option<String>,
+    pub system: Option<System>,
     pub temperature: Option<f32>,
     pub top_k: Option<u32>,
     pub top_p: Option<f32>,
     pub tools: Option<Vec<Tool>>,
     pub tool_choice: Option<ToolChoice>,
+    pub omit_model: bool,
+    pub force_single_tool: bool,
+    pub thinking: Option<Thinking>,
+    /// Start of the assistant's reply, added as the last message when the request is built.
+    pub prefill: Option<String>,
+    /// Why the last value given to [`model`](Self::model) is not a model, reported by `build`.
+    model_error: Option<BuildError>,
+    /// Why the file given to [`system_from_file`](Self::system_from_file) could not be read,
+    /// reported by `build`.
+    system_error: Option<BuildError>,
 }
 
-impl ClaudeRequestBuilder {
-    /// Creates a new ClaudeRequestBuilder.
-    pub fn new() -> Self {
-        Self::default()
+/// Checks the sampling parameters of a request: each in range, and not both temperature and
+/// top_p.
+fn check_sampling(
+    temperature: Option<f32>,
+    top_p: Option<f32>,
+    top_k: Option<u32>,
+) -> Result<(), BuildError> {
+    if let Some(temperature) = temperature {
+        if !(0.0..=1.0).contains(&temperature) {
+            return Err(BuildError::InvalidTemperature(temperature));
+        }
     }
-
-    /// Sets the model for the request.
-    pub fn model(mut self, model: Model) -> Self {
-        self.model = Some(model);
-        self
+    if let Some(top_p) = top_p {
+        if !(0.0..=1.0).contains(&top_p) {
+            return Err(BuildError::InvalidTopP(top_p));
+        }
+    }
+    if top_k == Some(0) {
+        return Err(BuildError::InvalidTopK(0));
+    }
+    if temperature.is_some() && top_p.is_some() {
+        return Err(BuildError::TemperatureWithTopP);
+    }
+
+    Ok(())
+}
+
+/// Checks that each turn after an assistant turn with tool uses answers exactly those tool
+/// uses, and that tool results only follow the assistant turn they answer.
+///
+/// A final assistant turn is not checked, since its tool uses have not been run yet.
+pub(crate) fn check_tool_results(messages: &[Message]) -> Result<(), BuildError> {
+    for (i, message) in messages.iter().enumerate() {
+        let result_ids: Vec<&str> = message
+            .content
+            .iter()
+            .filter_map(|content| match content {
+                ContentType::ToolResult(result) => Some(result.tool_use_id.as_str()),
+                _ => None,
+            })
+            .collect();
+        let tool_use_ids: Vec<&str> = match i.checked_sub(1).map(|previous| &messages[previous]) {
+            Some(previous) if previous.role == Role::Assistant => previous
+                .content
+                .iter()
+                .filter_map(|content| match content {
+                    ContentType::ToolUse(tool_use) => Some(tool_use.id.as_str()),
+                    _ => None,
+                })
+                .collect(),
+            _ => Vec::new(),
+        };
+        if result_ids.is_empty() && tool_use_ids.is_empty() {
+            continue;
+        }
+
+        if let Some(id) = result_ids.iter().find(|id| !tool_use_ids.contains(id)) {
+            return Err(BuildError::UnmatchedToolResult(id.to_string()));
+        }
+        if let Some(id) = tool_use_ids.iter().find(|id| !result_ids.contains(id)) {
+            return Err(BuildError::MissingToolResult(id.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+impl From<Vec<Message>> for ClaudeRequestBuilder {
+    fn from(messages: Vec<Message>) -> Self {
+        Self::new().messages(messages)
+    }
+}
+
+impl ClaudeRequestBuilder {
+    /// Creates a new ClaudeRequestBuilder.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the model for the request, from a [`Model`] or its API identifier.
+    ///
+    /// An unknown identifier is reported by [`build`](Self::build) as
+    /// [`BuildError::UnknownModel`]; use [`Model::Other`] to name models this crate does not
+    /// know.
+    pub fn model<M>(mut self, model: M) -> Self
+    where
+        M: TryInto<Model>,
+        M::Error: Into<BuildError>,
+    {
+        match model.try_into() {
+            Ok(model) => {
+                self.model = Some(model);
+                self.model_error = None;
+            }
+            Err(error) => {
+                self.model = None;
+                self.model_error = Some(error.into());
+            }
+        }
+        self
     }
 
     /// Adds a message to the request.
@@ -253,36 +1254,173 @@ impl ClaudeRequestBuilder {
         self
     }
 
+    /// Adds a user message containing a single text block.
+    pub fn user(self, text: impl Into<String>) -> Self {
+        self.add_message(Role::User, vec![ContentType::text(text)])
+    }
+
+    /// Adds an assistant message containing a single text block.
+    pub fn assistant(self, text: impl Into<String>) -> Self {
+        self.add_message(Role::Assistant, vec![ContentType::text(text)])
+    }
+
+    /// Starts the assistant's reply with `text`, e.g. `{` to get JSON without defining a tool.
+    ///
+    /// The text is added as an assistant message after all other messages when the request is
+    /// built, so the turn before it must be the user's. The response continues from the end
+    /// of the prefill; see [`ClaudeRequest::prefill`] to put the two back together.
+    pub fn prefill(mut self, text: impl Into<String>) -> Self {
+        self.prefill = Some(text.into());
+        self
+    }
+
+    /// Sets the messages for the request, replacing any added so far.
+    pub fn messages(mut self, messages: Vec<Message>) -> Self {
+        self.messages = messages;
+        self
+    }
+
+    /// Appends messages to the request, e.g. when replaying a stored conversation.
+    pub fn extend_messages(mut self, messages: impl IntoIterator<Item = Message>) -> Self {
+        self.messages.extend(messages);
+        self
+    }
+
+    /// Adds a call to the tool `name` to the request.
+    ///
+    /// The call is appended to the last message if it is an assistant turn, otherwise a new
+    /// assistant turn is started.
+    pub fn add_tool_use(
+        mut self,
+        id: impl Into<String>,
+        name: impl Into<String>,
+        input: Value,
+    ) -> Self {
+        let tool_use = ContentType::ToolUse(ToolUse::new(id, name, input));
+        match self.messages.last_mut() {
+            Some(message) if message.role == Role::Assistant => message.content.push(tool_use),
+            _ => self.messages.push(Message {
+                role: Role::Assistant,
+                content: vec![tool_use],
+            }),
+        }
+        self
+    }
+
+    /// Adds the result of the tool use with id `tool_use_id` to the request.
+    ///
+    /// The result is appended to the last message if it is a user turn holding only tool
+    /// results, so results for parallel tool uses share a turn; otherwise a new user turn is
+    /// started.
+    pub fn add_tool_result(
+        mut self,
+        tool_use_id: impl Into<String>,
+        content: impl Into<ToolResultContent>,
+    ) -> Self {
+        let result = ContentType::ToolResult(ToolResult::new(tool_use_id, content));
+        match self.messages.last_mut() {
+            Some(message)
+                if message.role == Role::User
+                    && !message.content.is_empty()
+                    && message
+                        .content
+                        .iter()
+                        .all(|content| matches!(content, ContentType::ToolResult(_))) =>
+            {
+                message.content.push(result)
+            }
+            _ => self.messages.push(Message {
+                role: Role::User,
+                content: vec![result],
+            }),
+        }
+        self
+    }
+
+    /// Adds a user turn containing only tool results.
+    ///
+    /// The turn must directly follow an assistant turn and answer each of its tool uses, which
+    /// is checked by [`validate_now`](Self::validate_now) and [`build`](Self::build).
+    pub fn tool_result_turn(mut self, results: Vec<ToolResult>) -> Self {
+        self.messages.push(Message {
+            role: Role::User,
+            content: results.into_iter().map(ContentType::ToolResult).collect(),
+        });
+        self
+    }
+
     /// Sets the maximum number of tokens to generate.
     pub fn max_tokens(mut self, max_tokens: u32) -> Self {
         self.max_tokens = Some(max_tokens);
         self
     }
 
-    /// Adds metadata to the request.
+    /// Adds metadata to the request, replacing any set before.
     pub fn metadata(mut self, metadata: HashMap<String, String>) -> Self {
         self.metadata = Some(metadata);
         self
     }
 
+    /// Sets the `user_id` metadata, an opaque identifier for the end user of the request.
+    ///
+    /// Other metadata set with [`metadata`](Self::metadata) is kept, as long as it is set first.
+    pub fn user_id(mut self, user_id: impl Into<String>) -> Self {
+        self.metadata
+            .get_or_insert_with(HashMap::new)
+            .insert("user_id".to_string(), user_id.into());
+        self
+    }
+
     /// Sets custom stop sequences for the request.
+    ///
+    /// Each sequence must contain a non-whitespace character. The one that ended the response
+    /// is returned by [`ClaudeResponse::matched_stop_sequence`].
     pub fn stop_sequences(mut self, stop_sequences: Vec<String>) -> Self {
         self.stop_sequences = Some(stop_sequences);
         self
     }
 
     /// Sets whether to stream the response.
+    ///
+    /// [`Client::call`] always sends a non-streaming request and [`Client::call_stream`] a
+    /// streaming one, whatever this is set to.
     pub fn stream(mut self, stream: bool) -> Self {
         self.stream = Some(stream);
         self
     }
 
-    /// Sets the system prompt for the request.
-    pub fn system(mut self, system: impl Into<String>) -> Self {
+    /// Explicitly asks for a non-streaming response, sending `stream: false`.
+    pub fn non_streaming(self) -> Self {
+        self.stream(false)
+    }
+
+    /// Sets the system prompt for the request, from a string or a list of text blocks.
+    pub fn system(mut self, system: impl Into<System>) -> Self {
         self.system = Some(system.into());
+        self.system_error = None;
         self
     }
 
+    /// Sets the system prompt to the contents of the file at `path`, e.g. a prompt kept in a
+    /// `.md` file next to the code.
+    ///
+    /// A file that cannot be read is reported by [`build`](Self::build) as
+    /// [`BuildError::UnreadableFile`].
+    pub fn system_from_file(mut self, path: impl AsRef<Path>) -> Self {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(system) => self.system(system),
+            Err(error) => {
+                self.system = None;
+                self.system_error = Some(BuildError::UnreadableFile {
+                    path: path.to_path_buf(),
+                    message: error.to_string(),
+                });
+                self
+            }
+        }
+    }
+
     /// Sets the temperature for the request.
     pub fn temperature(mut self, temperature: f32) -> Self {
         self.temperature = Some(temperature);
@@ -313,16 +1451,100 @@ impl ClaudeRequestBuilder {
         self
     }
 
+    /// Enables extended thinking, letting the model reason with up to `budget_tokens` before
+    /// answering.
+    ///
+    /// The budget counts towards `max_tokens`, so `max_tokens` must be larger.
+    pub fn thinking(mut self, budget_tokens: u32) -> Self {
+        self.thinking = Some(Thinking::Enabled { budget_tokens });
+        self
+    }
+
+    /// Omits the `model` field from the serialized body.
+    ///
+    /// Some gateways pick the model from the route and reject a body-level `model`.
+    /// The model is still required and kept on the request for local use.
+    pub fn omit_model(mut self, omit_model: bool) -> Self {
+        self.omit_model = omit_model;
+        self
+    }
+
+    /// Runs the checks that apply to the request as built so far.
+    ///
+    /// Unlike [`build`](Self::build), missing required fields are not reported, so this can be
+    /// called at any point while the request is being constructed.
+    pub fn validate_now(&self) -> Result<(), BuildError> {
+        check_sampling(self.temperature, self.top_p, self.top_k)?;
+        let stop_sequences = self.stop_sequences.as_deref().unwrap_or_default();
+        for (index, sequence) in stop_sequences.iter().enumerate() {
+            if sequence.trim().is_empty() {
+                return Err(BuildError::InvalidStopSequence(index));
+            }
+        }
+        check_tool_results(&self.messages)?;
+
+        Ok(())
+    }
+
+    /// Forces the model to call the only tool in the request.
+    ///
+    /// `tool_choice` is set to that tool when the request is built, which fails unless
+    /// exactly one tool is present.
+    pub fn force_single_tool(mut self) -> Self {
+        self.force_single_tool = true;
+        self
+    }
+
     /// Builds the final request object.
-    pub fn build(self) -> Result<ClaudeRequest, String> {
+    ///
+    /// Fails if a required field is missing or a check from
+    /// [`validate_now`](Self::validate_now) does not pass.
+    pub fn build(mut self) -> Result<ClaudeRequest, BuildError> {
+        if let Some(error) = self.model_error.take().or(self.system_error.take()) {
+            return Err(error);
+        }
         if self.model.is_none() {
-            return Err("Model must be specified".to_string());
+            return Err(BuildError::MissingModel);
         }
         if self.messages.is_empty() {
-            return Err("At least one message must be added".to_string());
+            return Err(BuildError::NoMessages);
         }
         if self.max_tokens.is_none() {
-            return Err("Max tokens must be specified".to_string());
+            return Err(BuildError::MissingMaxTokens);
+        }
+        if let Some(prefill) = self.prefill.take() {
+            if prefill.ends_with(char::is_whitespace) {
+                return Err(BuildError::PrefillTrailingWhitespace);
+            }
+            if self.messages.last().map(|message| &message.role) == Some(&Role::Assistant) {
+                return Err(BuildError::RepeatedRole(self.messages.len()));
+            }
+            self.messages.push(Message {
+                role: Role::Assistant,
+                content: vec![ContentType::text(prefill)],
+            });
+        }
+        self.validate_now()?;
+
+        let mut tool_choice = self.tool_choice;
+        if self.force_single_tool {
+            match self.tools.as_deref() {
+                Some([tool]) => {
+                    tool_choice = Some(ToolChoice::Specific {
+                        name: tool.name.clone(),
+                        disable_parallel_tool_use: None,
+                    });
+                }
+                tools => {
+                    return Err(BuildError::NotSingleTool(tools.map_or(0, <[Tool]>::len)));
+                }
+            }
+        }
+        if let Some(ToolChoice::Specific { name, .. }) = &tool_choice {
+            let known = self.tools.iter().flatten().any(|tool| &tool.name == name);
+            if !known {
+                return Err(BuildError::UnknownToolChoice(name.clone()));
+            }
         }
 
         Ok(ClaudeRequest {
@@ -337,7 +1559,9 @@ impl ClaudeRequestBuilder {
             top_k: self.top_k,
             top_p: self.top_p,
             tools: self.tools,
-            tool_choice: self.tool_choice,
+            tool_choice,
+            omit_model: self.omit_model,
+            thinking: self.thinking,
         })
     }
 }
@@ -354,8 +1578,12 @@ pub struct ClaudeRequest {
     pub stop_sequences: Option<Vec<String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub stream: Option<bool>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub system: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_system",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub system: Option<System>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub temperature: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -366,6 +1594,11 @@ pub struct ClaudeRequest {
     pub tools: Option<Vec<Tool>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub tool_choice: Option<ToolChoice>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub thinking: Option<Thinking>,
+    /// When set, `model` is left out of the serialized body.
+    #[serde(skip)]
+    pub omit_model: bool,
 }
 
 impl Serialize for ClaudeRequest {
@@ -374,11 +1607,17 @@ impl Serialize for ClaudeRequest {
         S: serde::Serializer,
     {
         use serde::ser::SerializeStruct;
-        let mut state = serializer.serialize_struct("ClaudeRequest", 13)?;
-        state.serialize_field("model", &self.model)?;
+        let mut state = serializer.serialize_struct("ClaudeRequest", 14)?;
+        if !self.omit_model {
+            state.serialize_field("model", &self.model)?;
+        }
         state.serialize_field("messages", &self.messages)?;
         state.serialize_field("max_tokens", &self.max_tokens)?;
-        if let Some(ref metadata) = self.metadata {
+        if let Some(metadata) = self
+            .metadata
+            .as_ref()
+            .filter(|metadata| !metadata.is_empty())
+        {
             state.serialize_field("metadata", metadata)?;
         }
         if let Some(ref stop_sequences) = self.stop_sequences {
@@ -405,259 +1644,2265 @@ impl Serialize for ClaudeRequest {
         if let Some(ref tool_choice) = self.tool_choice {
             state.serialize_field("tool_choice", tool_choice)?;
         }
+        if let Some(ref thinking) = self.thinking {
+            state.serialize_field("thinking", thinking)?;
+        }
         state.end()
     }
 }
 
+/// Writes `value` as compact JSON with the keys of every object sorted.
+fn write_canonical(value: &Value, out: &mut String) {
+    match value {
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(value, out);
+            }
+            out.push(']');
+        }
+        Value::Object(fields) => {
+            let mut fields: Vec<_> = fields.iter().collect();
+            fields.sort_by_key(|(key, _)| *key);
+            out.push('{');
+            for (i, (key, value)) in fields.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&Value::from(key.as_str()).to_string());
+                out.push(':');
+                write_canonical(value, out);
+            }
+            out.push('}');
+        }
+        value => out.push_str(&value.to_string()),
+    }
+}
+
+/// Replaces the data of base64 image and document blocks in `value` with a short description.
+fn redact_base64(value: &mut Value) {
+    match value {
+        Value::Array(values) => values.iter_mut().for_each(redact_base64),
+        Value::Object(fields) => {
+            let kind = match fields.get("type").and_then(Value::as_str) {
+                Some(kind @ ("image" | "document")) => kind.to_string(),
+                _ => return fields.values_mut().for_each(redact_base64),
+            };
+            let Some(Value::Object(source)) = fields.get_mut("source") else {
+                return;
+            };
+            if source.get("type").and_then(Value::as_str) != Some("base64") {
+                return;
+            }
+            let media_type = source
+                .get("media_type")
+                .and_then(Value::as_str)
+                .unwrap_or("unknown")
+                .to_string();
+            if let Some(data) = source.get_mut("data") {
+                let size = data.as_str().map_or(0, |data| data.len() / 4 * 3);
+                *data = Value::from(format!(
+                    "<base64 {}: {} {}>",
+                    kind,
+                    format_size(size),
+                    media_type
+                ));
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Formats a number of bytes for people, e.g. `1.2MB`.
+fn format_size(bytes: usize) -> String {
+    match bytes {
+        0..=999 => format!("{}B", bytes),
+        1_000..=999_999 => format!("{:.1}KB", bytes as f64 / 1e3),
+        _ => format!("{:.1}MB", bytes as f64 / 1e6),
+    }
+}
+
 impl ClaudeRequest {
     /// Creates a new ClaudeRequestBuilder to start building a request.
     pub fn builder() -> ClaudeRequestBuilder {
         ClaudeRequestBuilder::new()
     }
 
+    /// Builds a request from role and text pairs, e.g. for tests or short multi-turn prompts.
+    ///
+    /// Turns do not have to alternate; call [`check_alternation`](Self::check_alternation) on
+    /// the request to require it.
+    pub fn from_dialogue(
+        model: Model,
+        max_tokens: u32,
+        dialogue: &[(Role, &str)],
+    ) -> Result<Self, BuildError> {
+        dialogue
+            .iter()
+            .fold(
+                Self::builder().model(model).max_tokens(max_tokens),
+                |builder, (role, text)| {
+                    builder.add_message(role.clone(), vec![ContentType::text(*text)])
+                },
+            )
+            .build()
+    }
+
+    /// Returns a copy of the request sent to `model`, e.g. to compare models on one prompt.
+    ///
+    /// The `with_*` methods leave the request they are called on unchanged. Those setting a
+    /// sampling parameter run the same checks as [`ClaudeRequestBuilder::build`].
+    pub fn with_model(&self, model: Model) -> Self {
+        Self {
+            model,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of the request with `max_tokens` replaced.
+    pub fn with_max_tokens(&self, max_tokens: u32) -> Self {
+        Self {
+            max_tokens,
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of the request with the system prompt replaced.
+    pub fn with_system(&self, system: impl Into<System>) -> Self {
+        Self {
+            system: Some(system.into()),
+            ..self.clone()
+        }
+    }
+
+    /// Returns a copy of the request with the temperature replaced, e.g. for a sweep.
+    ///
+    /// Fails like the builder if the temperature is out of range or top_p is set.
+    pub fn with_temperature(&self, temperature: f32) -> Result<Self, BuildError> {
+        check_sampling(Some(temperature), self.top_p, self.top_k)?;
+        Ok(Self {
+            temperature: Some(temperature),
+            ..self.clone()
+        })
+    }
+
+    /// Returns a copy of the request with top_k replaced.
+    ///
+    /// Fails like the builder if top_k is 0.
+    pub fn with_top_k(&self, top_k: u32) -> Result<Self, BuildError> {
+        check_sampling(self.temperature, self.top_p, Some(top_k))?;
+        Ok(Self {
+            top_k: Some(top_k),
+            ..self.clone()
+        })
+    }
+
+    /// Returns a copy of the request with top_p replaced.
+    ///
+    /// Fails like the builder if top_p is out of range or the temperature is set.
+    pub fn with_top_p(&self, top_p: f32) -> Result<Self, BuildError> {
+        check_sampling(self.temperature, Some(top_p), self.top_k)?;
+        Ok(Self {
+            top_p: Some(top_p),
+            ..self.clone()
+        })
+    }
+
+    /// Checks that user and assistant turns alternate.
+    ///
+    /// Fails with [`BuildError::RepeatedRole`] at the first message with the same role as the
+    /// one before it.
+    pub fn check_alternation(&self) -> Result<(), BuildError> {
+        match self
+            .messages
+            .windows(2)
+            .position(|pair| pair[0].role == pair[1].role)
+        {
+            Some(index) => Err(BuildError::RepeatedRole(index + 1)),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the text the assistant's reply was started with, if the request ends with an
+    /// assistant turn.
+    ///
+    /// The response only contains what comes after it, so the full reply is the prefill
+    /// followed by the response's [`text`](ClaudeResponse::text).
+    pub fn prefill(&self) -> Option<&str> {
+        match self.messages.last()? {
+            Message {
+                role: Role::Assistant,
+                content,
+            } => match content.last()? {
+                ContentType::Text { text, .. } => Some(text),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Returns a hash of the request body, e.g. for use as a cache or idempotency key.
+    ///
+    /// The hash is computed over the serialized request with object keys sorted, so equal
+    /// requests hash the same regardless of the order fields or metadata were set in. It uses
+    /// FNV-1a, which is stable across processes and Rust versions but not cryptographic.
+    pub fn stable_hash(&self) -> u64 {
+        const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+        const PRIME: u64 = 0x100000001b3;
+
+        let mut canonical = String::new();
+        // Serializing a request only fails for maps with non-string keys, which it has none of.
+        let body = serde_json::to_value(self).unwrap_or(Value::Null);
+        write_canonical(&body, &mut canonical);
+
+        canonical.bytes().fold(OFFSET_BASIS, |hash, byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(PRIME)
+        })
+    }
+
+    /// Returns the request body with the data of base64 images and documents replaced, e.g.
+    /// `"<base64 image: 1.2MB image/png>"`, so it can be logged.
+    ///
+    /// Everything else, including text and the shape of each block, is kept as is.
+    pub fn redacted_for_logging(&self) -> Value {
+        // Serializing a request only fails for maps with non-string keys, which it has none of.
+        let mut body = serde_json::to_value(self).unwrap_or(Value::Null);
+        redact_base64(&mut body);
+        body
+    }
+
+    /// Beta features the request relies on, sent in the `anthropic-beta` header.
+    pub(crate) fn betas(&self) -> Vec<&'static str> {
+        let mut betas = Vec::new();
+
+        let cache_controls: Vec<&CacheControl> = self
+            .messages
+            .iter()
+            .flat_map(|message| &message.content)
+            .chain(self.system.iter().flat_map(System::blocks))
+            .filter_map(ContentType::cache_control)
+            .collect();
+        if !cache_controls.is_empty() {
+            betas.push(PROMPT_CACHING_BETA);
+        }
+        let extended = cache_controls.iter().any(|control| {
+            matches!(
+                control,
+                CacheControl::Ephemeral {
+                    ttl: Some(CacheTtl::OneHour)
+                }
+            )
+        });
+        if extended {
+            betas.push(EXTENDED_CACHE_TTL_BETA);
+        }
+
+        betas
+    }
+
     /// Invoke the Claude Chat API.
-    pub async fn call(&self) -> Result<String> {
-        // TODO: Result<ClaudeResponse>
-        let api_key = std::env::var("ANTHROPIC_API_KEY").expect("ANTHROPIC_API_KEY must be set");
-        let client = reqwest::Client::new();
+    ///
+    /// Reads the API key from `ANTHROPIC_API_KEY`; use a [`Client`] to pass one explicitly.
+    pub async fn call(&self) -> Result<ClaudeResponse> {
+        Client::new().call(self).await
+    }
+
+    /// Invoke the Claude Chat API and stream the response as it is generated.
+    ///
+    /// Reads the API key from `ANTHROPIC_API_KEY`; use a [`Client`] to pass one explicitly.
+    pub async fn call_stream(&self) -> Result<impl futures::Stream<Item = Result<StreamEvent>>> {
+        Client::new().call_stream(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::message_json;
+    use pretty_assertions::assert_eq;
+    use schemars::JsonSchema;
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    struct GetStockPrice {
+        ticker: String,
+    }
+
+    impl ToolBuilder for GetStockPrice {
+        fn name() -> &'static str {
+            "get_stock_price"
+        }
+
+        fn description() -> Option<&'static str> {
+            Some("Get the current stock price for a given ticker symbol.")
+        }
+    }
+
+    #[test]
+    fn test_models_order_by_tier_then_version() {
+        let mut models = vec![
+            Model::Haiku3,
+            Model::Opus3,
+            Model::Sonnet45,
+            Model::Other("claude-next".to_string()),
+            Model::Sonnet37,
+            Model::Opus41,
+            Model::Haiku45,
+        ];
+        // Most capable first, newest first within a tier, unknown models last.
+        models.sort_by_key(|model| std::cmp::Reverse((model.tier(), model.version())));
+
+        assert_eq!(
+            models,
+            vec![
+                Model::Opus41,
+                Model::Opus3,
+                Model::Sonnet45,
+                Model::Sonnet37,
+                Model::Haiku45,
+                Model::Haiku3,
+                Model::Other("claude-next".to_string()),
+            ]
+        );
+        assert!(ModelTier::Haiku < ModelTier::Sonnet && ModelTier::Sonnet < ModelTier::Opus);
+    }
+
+    #[test]
+    fn test_latest_generation_models_round_trip() -> Result<()> {
+        let models = [
+            (Model::Opus4, "claude-opus-4-20250514"),
+            (Model::Sonnet4, "claude-sonnet-4-20250514"),
+            (Model::Opus41, "claude-opus-4-1-20250805"),
+            (Model::Sonnet45, "claude-sonnet-4-5-20250929"),
+            (Model::Haiku45, "claude-haiku-4-5-20251001"),
+            (Model::Opus45, "claude-opus-4-5-20251101"),
+        ];
+
+        for (model, id) in models {
+            assert_eq!(serde_json::to_value(&model)?, serde_json::json!(id));
+            assert_eq!(
+                serde_json::from_value::<Model>(serde_json::json!(id))?,
+                model
+            );
+        }
+
+        let mut message = message_json(
+            serde_json::json!([{"type": "text", "text": "Hello!"}]),
+            "end_turn",
+        );
+        message["model"] = serde_json::json!("claude-opus-4-1-20250805");
+        let response: ClaudeResponse = serde_json::from_value(message)?;
+        assert_eq!(response.model, Model::Opus41);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_from_str_round_trips() -> Result<()> {
+        let models = [
+            (Model::Sonnet35V2, "claude-3-5-sonnet-20241022"),
+            (Model::Haiku35, "claude-3-5-haiku-20241022"),
+            (Model::Sonnet37, "claude-3-7-sonnet-20250219"),
+            (Model::Sonnet35Latest, "claude-3-5-sonnet-latest"),
+            (Model::Haiku35Latest, "claude-3-5-haiku-latest"),
+            (Model::Sonnet37Latest, "claude-3-7-sonnet-latest"),
+            (Model::Opus3Latest, "claude-3-opus-latest"),
+        ];
+
+        for (model, id) in models {
+            assert_eq!(id.parse::<Model>(), Ok(model.clone()));
+            assert_eq!(serde_json::to_value(&model)?, serde_json::json!(id));
+        }
+
+        assert_eq!(
+            "claude-2".parse::<Model>(),
+            Err(ParseModelError("claude-2".to_string()))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_model_string_round_trip() {
+        for model in Model::KNOWN {
+            assert_eq!(model.as_str().parse::<Model>(), Ok(model.clone()));
+            assert_eq!(model.to_string().parse::<Model>(), Ok(model.clone()));
+        }
+
+        let other = Model::Other("claude-next".to_string());
+        assert_eq!(other.as_str(), "claude-next");
+        assert!(other.as_str().parse::<Model>().is_err());
+    }
+
+    #[test]
+    fn test_model_id() -> Result<()> {
+        for model in Model::KNOWN {
+            assert_eq!(serde_json::to_value(&model)?, serde_json::json!(model.id()));
+            assert_eq!(model.to_string(), model.id());
+        }
+
+        assert_eq!(Model::Haiku3.id(), "claude-3-haiku-20240307");
+        assert_eq!(Model::Opus45.id(), "claude-opus-4-5-20251101");
+        assert_eq!(Model::Other("claude-next".to_string()).id(), "claude-next");
+        assert_eq!(
+            Model::Other("claude-next".to_string()).to_string(),
+            "claude-next"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_model_in_response_is_preserved() -> Result<()> {
+        let mut message = message_json(
+            serde_json::json!([{"type": "text", "text": "Hello!"}]),
+            "end_turn",
+        );
+        message["model"] = serde_json::json!("claude-4-some-future-20260101");
+        let response: ClaudeResponse = serde_json::from_value(message)?;
+
+        assert_eq!(
+            response.model,
+            Model::Other("claude-4-some-future-20260101".to_string())
+        );
+        assert_eq!(
+            serde_json::to_value(&response.model)?,
+            serde_json::json!("claude-4-some-future-20260101")
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stop_reasons_round_trip() -> Result<()> {
+        let reasons = [
+            (StopReason::EndTurn, "end_turn"),
+            (StopReason::MaxTokens, "max_tokens"),
+            (StopReason::StopSequence, "stop_sequence"),
+            (StopReason::ToolUse, "tool_use"),
+            (StopReason::PauseTurn, "pause_turn"),
+            (StopReason::Refusal, "refusal"),
+            (
+                StopReason::Other("model_context_window_exceeded".to_string()),
+                "model_context_window_exceeded",
+            ),
+        ];
+
+        for (reason, value) in reasons {
+            assert_eq!(serde_json::to_value(&reason)?, serde_json::json!(value));
+            assert_eq!(
+                serde_json::from_value::<StopReason>(serde_json::json!(value))?,
+                reason
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_thinking_request() {
+        let request = ClaudeRequest::builder()
+            .model(Model::Sonnet37)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Hello".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(16000)
+            .thinking(10000)
+            .build()
+            .expect("Failed to build request");
+
+        assert_request_eq!(
+            request,
+            serde_json::json!({
+                "model": "claude-3-7-sonnet-20250219",
+                "messages": [{"role": "user", "content": [{"type": "text", "text": "Hello"}]}],
+                "max_tokens": 16000,
+                "thinking": {"type": "enabled", "budget_tokens": 10000}
+            })
+        );
+    }
+
+    #[test]
+    fn test_thinking_block_round_trips() -> Result<()> {
+        let block = serde_json::json!({
+            "type": "thinking",
+            "thinking": "The user wants the 1982 result, so I should look it up.",
+            "signature": "WaUjzkypQ2mUEVM36O2TxuC06KN8xyfbJwyem2dw3URve"
+        });
+
+        let content: ContentType = serde_json::from_value(block.clone())?;
+
+        assert!(matches!(
+            &content,
+            ContentType::Thinking { signature, .. }
+                if signature == "WaUjzkypQ2mUEVM36O2TxuC06KN8xyfbJwyem2dw3URve"
+        ));
+        assert_eq!(serde_json::to_value(&content)?, block);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_try_from_value() -> Result<()> {
+        let text = ContentType::try_from(json!({"type": "text", "text": "Hello"}))?;
+        assert!(matches!(text, ContentType::Text { text, .. } if text == "Hello"));
 
-        let mut headers = HeaderMap::new();
-        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
-        headers.insert("anthropic-version", HeaderValue::from_static("2023-06-01"));
-        headers.insert("x-api-key", HeaderValue::from_str(&api_key)?);
+        let tool_use = ContentType::try_from(json!({
+            "type": "tool_use",
+            "id": "toolu_01",
+            "name": "get_weather",
+            "input": {"location": "San Francisco, CA"}
+        }))?;
+        assert!(matches!(tool_use, ContentType::ToolUse(tool_use) if tool_use.id == "toolu_01"));
 
-        let body = serde_json::to_string(&self)?;
+        let unknown = ContentType::try_from(json!({"type": "container_upload", "file_id": "f"}))?;
+        assert!(matches!(unknown, ContentType::Unknown(_)));
+
+        assert!(ContentType::try_from(json!({"type": "text"})).is_err());
+        assert!(ContentType::try_from(json!("Hello")).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_message_try_from_value() -> Result<()> {
+        let message = Message::try_from(json!({
+            "role": "user",
+            "content": [
+                {"type": "tool_result", "tool_use_id": "toolu_01", "content": "15 degrees"},
+                {"type": "text", "text": "Is that warm?"}
+            ]
+        }))?;
+        assert_eq!(message.role, Role::User);
+        assert!(matches!(&message.content[0], ContentType::ToolResult(_)));
+        assert!(matches!(&message.content[1], ContentType::Text { .. }));
+
+        let message = Message::try_from(json!({"role": "assistant", "content": "Hi there!"}))?;
+        assert!(matches!(
+            &message.content[..],
+            [ContentType::Text { text, .. }] if text == "Hi there!"
+        ));
+
+        assert!(
+            Message::try_from(json!({"role": "user", "content": [{"type": "image"}]})).is_err()
+        );
+        assert!(Message::try_from(json!({"role": "system", "content": "Hi"})).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_server_tool_use_block() -> Result<()> {
+        let block = serde_json::json!({
+            "type": "server_tool_use",
+            "id": "srvtoolu_01",
+            "name": "web_search",
+            "input": {"query": "1982 Super Bowl winner"}
+        });
+
+        let content: ContentType = serde_json::from_value(block.clone())?;
+
+        assert!(matches!(
+            &content,
+            ContentType::ServerToolUse { id, name, input }
+                if id == "srvtoolu_01"
+                    && name == "web_search"
+                    && input["query"] == "1982 Super Bowl winner"
+        ));
+        assert_eq!(serde_json::to_value(&content)?, block);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_content_block_is_kept() -> Result<()> {
+        let block = serde_json::json!({
+            "type": "future_block",
+            "data": {"anything": [1, 2, 3]}
+        });
+        let response: ClaudeResponse = serde_json::from_value(message_json(
+            serde_json::json!([block.clone(), {"type": "text", "text": "Hello!"}]),
+            "end_turn",
+        ))?;
+
+        assert!(matches!(&response.content[0], ContentType::Unknown(value) if *value == block));
+        assert!(matches!(&response.content[1], ContentType::Text { text, .. } if text == "Hello!"));
+        assert_eq!(serde_json::to_value(&response.content[0])?, block);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_usage_with_cache_fields() -> Result<()> {
+        let mut message = message_json(
+            serde_json::json!([{"type": "text", "text": "Hello!"}]),
+            "end_turn",
+        );
+        message["usage"]["cache_creation_input_tokens"] = serde_json::json!(2048);
+        message["usage"]["cache_read_input_tokens"] = serde_json::json!(0);
+        let response: ClaudeResponse = serde_json::from_value(message)?;
+
+        assert_eq!(response.usage.cache_creation_input_tokens, Some(2048));
+        assert_eq!(response.usage.cache_read_input_tokens, Some(0));
+        assert_eq!(response.usage.total_tokens(), 12);
+        assert_eq!(response.usage.thinking_tokens, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_usage_with_thinking_tokens() -> Result<()> {
+        let mut message = message_json(
+            serde_json::json!([
+                {"type": "thinking", "thinking": "31 + 10 = 41", "signature": "EuYBCkQYAiJA"},
+                {"type": "text", "text": "41 points were scored."}
+            ]),
+            "end_turn",
+        );
+        message["usage"] =
+            serde_json::json!({"input_tokens": 40, "output_tokens": 350, "thinking_tokens": 312});
+        let response: ClaudeResponse = serde_json::from_value(message)?;
+
+        assert_eq!(response.usage.thinking_tokens, Some(312));
+        assert_eq!(response.usage.total_tokens(), 390);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_response_into_parts() -> Result<()> {
+        let response: ClaudeResponse = serde_json::from_value(message_json(
+            serde_json::json!([{"type": "text", "text": "Hello!"}]),
+            "end_turn",
+        ))?;
+
+        let (content, meta) = response.into_parts();
+
+        assert!(matches!(&content[..], [ContentType::Text { text, .. }] if text == "Hello!"));
+        assert_eq!(meta.id, "msg_01");
+        assert_eq!(meta.model, Model::Haiku3);
+        assert_eq!(meta.stop_reason, Some(StopReason::EndTurn));
+        assert_eq!(meta.usage.output_tokens, 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_responses_compare_equal() -> Result<()> {
+        let expected = ClaudeResponse {
+            id: "msg_01".to_string(),
+            response_type: "message".to_string(),
+            role: Role::Assistant,
+            content: vec![
+                ContentType::text("Let me check."),
+                ContentType::ToolUse(ToolUse::new(
+                    "toolu_01",
+                    "get_weather",
+                    serde_json::json!({"location": "Paris"}),
+                )),
+            ],
+            model: Model::Haiku3,
+            stop_reason: Some(StopReason::ToolUse),
+            stop_sequence: None,
+            usage: Usage {
+                input_tokens: 10,
+                output_tokens: 20,
+                cache_creation_input_tokens: None,
+                cache_read_input_tokens: None,
+                thinking_tokens: None,
+            },
+            incomplete: false,
+            raw: None,
+        };
+        let response: ClaudeResponse = serde_json::from_value(serde_json::to_value(&expected)?)?;
+
+        assert_eq!(response, expected);
+        assert_ne!(
+            response,
+            ClaudeResponse {
+                stop_reason: Some(StopReason::EndTurn),
+                ..expected.clone()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_redacted_for_logging() {
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![
+                    ContentType::Image {
+                        source: ImageSource::Base64 {
+                            media_type: "image/png".to_string(),
+                            data: "A".repeat(1_600_000),
+                        },
+                    },
+                    ContentType::text("What is in this image?"),
+                ],
+            )
+            .max_tokens(100)
+            .build()
+            .expect("Failed to build request");
+
+        assert_eq!(
+            request.redacted_for_logging()["messages"][0]["content"],
+            serde_json::json!([
+                {
+                    "type": "image",
+                    "source": {
+                        "type": "base64",
+                        "media_type": "image/png",
+                        "data": "<base64 image: 1.2MB image/png>"
+                    }
+                },
+                {"type": "text", "text": "What is in this image?"}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_image_source_serialization() -> Result<()> {
+        let base64 = ContentType::Image {
+            source: ImageSource::Base64 {
+                media_type: "image/png".to_string(),
+                data: "iVBORw0KGgo=".to_string(),
+            },
+        };
+        let url = ContentType::image_from_url("https://example.com/cat.jpg");
+
+        assert_eq!(
+            serde_json::to_value(&base64)?,
+            serde_json::json!({
+                "type": "image",
+                "source": {"type": "base64", "media_type": "image/png", "data": "iVBORw0KGgo="}
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(&url)?,
+            serde_json::json!({
+                "type": "image",
+                "source": {"type": "url", "url": "https://example.com/cat.jpg"}
+            })
+        );
+        assert!(matches!(
+            serde_json::from_value(serde_json::to_value(&base64)?)?,
+            ContentType::Image {
+                source: ImageSource::Base64 { .. }
+            }
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_request_builder() {
+        let stock_price_tool = Tool::new::<GetStockPrice>();
+
+        let request = ClaudeRequest::builder()
+            .model(Model::Opus3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "What's the current stock price of Apple?".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(100)
+            .temperature(0.7)
+            .tools(vec![stock_price_tool])
+            .tool_choice(ToolChoice::Auto {
+                disable_parallel_tool_use: Some(true),
+            })
+            .build()
+            .expect("Failed to build request");
+
+        assert_eq!(request.model, Model::Opus3);
+        assert_eq!(request.max_tokens, 100);
+        assert_eq!(request.temperature, Some(0.7));
+        assert!(request.tools.is_some());
+        assert!(matches!(
+            request.tool_choice,
+            Some(ToolChoice::Auto {
+                disable_parallel_tool_use: Some(true)
+            })
+        ));
+    }
+
+    #[test]
+    fn test_missing_required_fields() {
+        assert_eq!(
+            ClaudeRequest::builder()
+                .user("Hello")
+                .max_tokens(10)
+                .build()
+                .unwrap_err(),
+            BuildError::MissingModel
+        );
+        assert_eq!(
+            ClaudeRequest::builder()
+                .model(Model::Haiku3)
+                .max_tokens(10)
+                .build()
+                .unwrap_err(),
+            BuildError::NoMessages
+        );
+        assert_eq!(
+            ClaudeRequest::builder()
+                .model(Model::Haiku3)
+                .user("Hello")
+                .build()
+                .unwrap_err(),
+            BuildError::MissingMaxTokens
+        );
+        assert!(matches!(
+            ClaudeRequest::builder()
+                .model(Model::Haiku3)
+                .user("Hello")
+                .max_tokens(10)
+                .temperature(2.0)
+                .build(),
+            Err(BuildError::InvalidTemperature(_))
+        ));
+    }
+
+    #[test]
+    fn test_minimal_valid_request() {
+        let request = ClaudeRequest::builder()
+            .model(Model::Opus3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Hello".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(10)
+            .build();
+
+        assert!(request.is_ok());
+    }
+
+    #[test]
+    fn test_request_with_all_params() {
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Hello".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(10)
+            .temperature(0.7)
+            .top_k(10)
+            .stream(true)
+            .system("You are a helpful assistant.")
+            .stop_sequences(vec!["STOP".to_string()])
+            .metadata(std::collections::HashMap::new())
+            .build();
+
+        assert!(request.is_ok());
+    }
+
+    fn system_request(system: impl Into<System>) -> ClaudeRequest {
+        ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Hello".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(10)
+            .system(system)
+            .build()
+            .expect("Failed to build request")
+    }
+
+    #[test]
+    fn test_system_as_string() {
+        let request = system_request("You are a helpful assistant.");
+
+        assert_request_eq!(
+            request,
+            serde_json::json!({
+                "model": "claude-3-haiku-20240307",
+                "messages": [{"role": "user", "content": [{"type": "text", "text": "Hello"}]}],
+                "max_tokens": 10,
+                "system": "You are a helpful assistant."
+            })
+        );
+        assert!(request.betas().is_empty());
+    }
+
+    #[test]
+    fn test_system_as_blocks() {
+        let request = system_request(vec![ContentType::Text {
+            text: "You are a helpful assistant.".to_string(),
+            cache_control: None,
+        }
+        .cached()]);
+
+        assert_request_eq!(
+            request,
+            serde_json::json!({
+                "model": "claude-3-haiku-20240307",
+                "messages": [{"role": "user", "content": [{"type": "text", "text": "Hello"}]}],
+                "max_tokens": 10,
+                "system": [{
+                    "type": "text",
+                    "text": "You are a helpful assistant.",
+                    "cache_control": {"type": "ephemeral"}
+                }]
+            })
+        );
+        assert_eq!(request.betas(), vec![PROMPT_CACHING_BETA]);
+    }
+
+    #[test]
+    fn test_one_hour_cache_ttl() -> Result<()> {
+        let request = system_request(vec![
+            ContentType::text("You are a helpful assistant.").cached_for(CacheTtl::OneHour)
+        ]);
+
+        assert_eq!(
+            serde_json::to_value(&request)?["system"][0]["cache_control"],
+            serde_json::json!({"type": "ephemeral", "ttl": "1h"})
+        );
+        assert_eq!(
+            request.betas(),
+            vec![PROMPT_CACHING_BETA, EXTENDED_CACHE_TTL_BETA]
+        );
+        assert!(serde_json::from_value::<CacheControl>(
+            serde_json::json!({"type": "ephemeral", "ttl": "2h"})
+        )
+        .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_system_round_trip() {
+        let blocks = vec![ContentType::text("You are a helpful assistant.").cached()];
+
+        for request in [
+            system_request("You are a helpful assistant."),
+            system_request(blocks),
+        ] {
+            let json = serde_json::to_value(&request).expect("Failed to serialize request");
+            let loaded: ClaudeRequest =
+                serde_json::from_value(json.clone()).expect("Failed to deserialize request");
+
+            assert_eq!(serde_json::to_value(&loaded).unwrap(), json);
+        }
+    }
+
+    #[test]
+    fn test_system_rejects_invalid_forms() {
+        let request = |system: Value| {
+            serde_json::from_value::<ClaudeRequest>(json!({
+                "model": "claude-3-haiku-20240307",
+                "messages": [{"role": "user", "content": [{"type": "text", "text": "Hello"}]}],
+                "max_tokens": 10,
+                "system": system
+            }))
+        };
+
+        let loaded = request(json!([{"type": "text", "text": "Be brief."}])).unwrap();
+        assert!(matches!(loaded.system, Some(System::Blocks(ref blocks)) if blocks.len() == 1));
+        assert!(request(json!(42)).is_err());
+        assert!(request(json!([{"type": "text"}])).is_err());
+    }
+
+    #[test]
+    fn test_validate_now_rejects_bad_temperature() {
+        let builder = ClaudeRequest::builder().temperature(1.5);
+
+        assert!(matches!(
+            builder.validate_now(),
+            Err(BuildError::InvalidTemperature(t)) if t == 1.5
+        ));
+        assert!(ClaudeRequest::builder()
+            .temperature(0.5)
+            .validate_now()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_sampling_parameters_are_validated() {
+        assert!(matches!(
+            ClaudeRequest::builder().top_p(1.2).validate_now(),
+            Err(BuildError::InvalidTopP(p)) if p == 1.2
+        ));
+        assert!(matches!(
+            ClaudeRequest::builder().top_p(-0.1).validate_now(),
+            Err(BuildError::InvalidTopP(_))
+        ));
+        assert!(matches!(
+            ClaudeRequest::builder().top_k(0).validate_now(),
+            Err(BuildError::InvalidTopK(0))
+        ));
+        assert!(matches!(
+            ClaudeRequest::builder()
+                .temperature(0.5)
+                .top_p(0.9)
+                .validate_now(),
+            Err(BuildError::TemperatureWithTopP)
+        ));
+        assert_eq!(
+            BuildError::InvalidTopP(1.2).to_string(),
+            "top_p must be between 0.0 and 1.0, got 1.2"
+        );
+
+        assert!(ClaudeRequest::builder()
+            .top_p(0.9)
+            .top_k(40)
+            .validate_now()
+            .is_ok());
+    }
+
+    #[test]
+    fn test_multiple_messages() {
+        let request = ClaudeRequest::builder()
+            .model(Model::Sonnet35)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Hello".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .add_message(
+                Role::Assistant,
+                vec![ContentType::Text {
+                    text: "Hi there!".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "How are you?".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(10)
+            .build();
+
+        assert!(request.is_ok());
+        assert_eq!(request.unwrap().messages.len(), 3);
+    }
+
+    #[test]
+    fn test_text_message_shortcuts() {
+        let request = ClaudeRequest::builder()
+            .model(Model::Sonnet35)
+            .user("hi")
+            .assistant("Hi there!")
+            .max_tokens(10)
+            .build()
+            .expect("Failed to build request");
+
+        assert_eq!(request.messages.len(), 2);
+        assert_eq!(request.messages[0].role, Role::User);
+        assert!(matches!(
+            &request.messages[0].content[..],
+            [ContentType::Text { text, cache_control: None }] if text == "hi"
+        ));
+        assert_eq!(request.messages[1].role, Role::Assistant);
+    }
+
+    #[test]
+    fn test_stable_hash_ignores_field_order() {
+        let metadata = |pairs: &[(&str, &str)]| {
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let first = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .user("Hello")
+            .max_tokens(10)
+            .temperature(0.5)
+            .metadata(metadata(&[("user_id", "42"), ("team", "research")]))
+            .build()
+            .expect("Failed to build request");
+        let second = ClaudeRequest::builder()
+            .metadata(metadata(&[("team", "research"), ("user_id", "42")]))
+            .temperature(0.5)
+            .max_tokens(10)
+            .user("Hello")
+            .model(Model::Haiku3)
+            .build()
+            .expect("Failed to build request");
+        let different = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .user("Goodbye")
+            .max_tokens(10)
+            .build()
+            .expect("Failed to build request");
+
+        assert_eq!(first.stable_hash(), second.stable_hash());
+        assert_ne!(first.stable_hash(), different.stable_hash());
+    }
+
+    #[test]
+    fn test_set_messages() {
+        let history: Vec<Message> = ["Hello", "Hi there!", "How are you?", "Good.", "Great!"]
+            .iter()
+            .enumerate()
+            .map(|(i, text)| Message {
+                role: if i % 2 == 0 {
+                    Role::User
+                } else {
+                    Role::Assistant
+                },
+                content: vec![ContentType::Text {
+                    text: text.to_string(),
+                    cache_control: None,
+                }],
+            })
+            .collect();
+
+        let request = ClaudeRequest::builder()
+            .model(Model::Sonnet35)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Replaced".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .messages(history.clone())
+            .max_tokens(10)
+            .build()
+            .expect("Failed to build request");
+
+        assert_eq!(request.messages.len(), 5);
+        assert!(matches!(
+            &request.messages[0].content[0],
+            ContentType::Text { text, .. } if text == "Hello"
+        ));
+
+        let request = ClaudeRequestBuilder::from(history[..4].to_vec())
+            .model(Model::Sonnet35)
+            .extend_messages(history[4..].to_vec())
+            .max_tokens(10)
+            .build()
+            .expect("Failed to build request");
+
+        assert_eq!(request.messages.len(), 5);
+    }
+
+    #[test]
+    fn test_metadata() {
+        let mut metadata = std::collections::HashMap::new();
+        metadata.insert("key".to_string(), "value".to_string());
+
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Hello".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(10)
+            .metadata(metadata.clone())
+            .build();
+
+        assert!(request.is_ok());
+        assert_eq!(request.unwrap().metadata, Some(metadata));
+    }
+
+    #[test]
+    fn test_model_from_enum_or_identifier() {
+        let build = |builder: ClaudeRequestBuilder| builder.user("Hello").max_tokens(10).build();
+
+        let from_enum = build(ClaudeRequest::builder().model(Model::Opus3)).unwrap();
+        let from_id = build(ClaudeRequest::builder().model("claude-3-opus-20240229")).unwrap();
+        assert_eq!(from_enum.model, Model::Opus3);
+        assert_eq!(from_id.model, Model::Opus3);
+
+        assert_eq!(
+            build(ClaudeRequest::builder().model("claude-3-opus")).unwrap_err(),
+            BuildError::UnknownModel("claude-3-opus".to_string())
+        );
+        // A later valid model replaces an invalid one.
+        let replaced = ClaudeRequest::builder()
+            .model("claude-3-opus")
+            .model(String::from("claude-3-haiku-20240307"));
+        assert_eq!(build(replaced).unwrap().model, Model::Haiku3);
+    }
+
+    #[test]
+    fn test_from_dialogue() -> std::result::Result<(), BuildError> {
+        let request = ClaudeRequest::from_dialogue(
+            Model::Haiku3,
+            100,
+            &[
+                (Role::User, "What is the capital of France?"),
+                (Role::Assistant, "Paris."),
+                (Role::User, "And of Italy?"),
+            ],
+        )?;
+        request.check_alternation()?;
+
+        let turns: Vec<(Role, String)> = request
+            .messages
+            .iter()
+            .map(|message| match &message.content[..] {
+                [ContentType::Text { text, .. }] => (message.role.clone(), text.clone()),
+                content => panic!("Unexpected content: {:?}", content),
+            })
+            .collect();
+        assert_eq!(
+            turns,
+            vec![
+                (Role::User, "What is the capital of France?".to_string()),
+                (Role::Assistant, "Paris.".to_string()),
+                (Role::User, "And of Italy?".to_string()),
+            ]
+        );
+        assert_eq!(request.max_tokens, 100);
+
+        let repeated = ClaudeRequest::from_dialogue(
+            Model::Haiku3,
+            100,
+            &[(Role::User, "Hello"), (Role::User, "Are you there?")],
+        )?;
+        assert_eq!(
+            repeated.check_alternation(),
+            Err(BuildError::RepeatedRole(1))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_methods_leave_original_unchanged() -> std::result::Result<(), BuildError> {
+        let base = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .user("Hello")
+            .max_tokens(10)
+            .build()?;
+
+        let longer = base.with_max_tokens(500).with_temperature(0.2)?;
+
+        assert_eq!(longer.max_tokens, 500);
+        assert_eq!(longer.temperature, Some(0.2));
+        assert_eq!(base.max_tokens, 10);
+        assert_eq!(base.temperature, None);
+        assert_eq!(longer.messages.len(), base.messages.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_methods_validate_sampling() -> std::result::Result<(), BuildError> {
+        let base = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .user("Hello")
+            .max_tokens(10)
+            .build()?;
+
+        assert_eq!(
+            base.with_temperature(1.5).unwrap_err(),
+            BuildError::InvalidTemperature(1.5)
+        );
+        assert_eq!(
+            base.with_top_p(0.9)?.with_temperature(0.2).unwrap_err(),
+            BuildError::TemperatureWithTopP
+        );
+        assert_eq!(base.with_top_k(0).unwrap_err(), BuildError::InvalidTopK(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stop_sequences() -> Result<()> {
+        let error = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .user("Count to ten.")
+            .max_tokens(100)
+            .stop_sequences(vec!["five".to_string(), " ".to_string()])
+            .build()
+            .unwrap_err();
+        assert_eq!(error, BuildError::InvalidStopSequence(1));
+
+        let mut message = message_json(
+            serde_json::json!([{"type": "text", "text": "one, two, three, four, "}]),
+            "stop_sequence",
+        );
+        message["stop_sequence"] = serde_json::json!("five");
+        let response: ClaudeResponse = serde_json::from_value(message)?;
+        assert_eq!(response.matched_stop_sequence(), Some("five"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_system_from_file() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("tyrell-system-{}.md", std::process::id()));
+        std::fs::write(&path, "You are an expert financial analyst.")?;
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .system_from_file(&path)
+            .user("Hello")
+            .max_tokens(10)
+            .build();
+        std::fs::remove_file(&path)?;
+
+        assert!(matches!(
+            request?.system,
+            Some(System::Text(text)) if text == "You are an expert financial analyst."
+        ));
+
+        let error = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .system_from_file(&path)
+            .user("Hello")
+            .max_tokens(10)
+            .build()
+            .unwrap_err();
+        assert!(
+            matches!(error, BuildError::UnreadableFile { path: missing, .. } if missing == path)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_user_id() {
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .user("Hello")
+            .max_tokens(10)
+            .user_id("abc")
+            .build()
+            .expect("Failed to build request");
+
+        assert_request_eq!(
+            request,
+            serde_json::json!({
+                "model": "claude-3-haiku-20240307",
+                "messages": [{"role": "user", "content": [{"type": "text", "text": "Hello"}]}],
+                "max_tokens": 10,
+                "metadata": {"user_id": "abc"}
+            })
+        );
+    }
+
+    #[test]
+    fn test_prefill_is_sent_last() {
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .prefill("{")
+            .user("List three colors as JSON.")
+            .max_tokens(100)
+            .build()
+            .expect("Failed to build request");
+
+        assert_eq!(request.prefill(), Some("{"));
+        assert_request_eq!(
+            request,
+            serde_json::json!({
+                "model": "claude-3-haiku-20240307",
+                "messages": [
+                    {"role": "user", "content": [{"type": "text", "text": "List three colors as JSON."}]},
+                    {"role": "assistant", "content": [{"type": "text", "text": "{"}]}
+                ],
+                "max_tokens": 100
+            })
+        );
+
+        let error = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .user("Hello")
+            .assistant("Hi!")
+            .prefill("{")
+            .max_tokens(100)
+            .build()
+            .unwrap_err();
+        assert_eq!(error, BuildError::RepeatedRole(2));
+        let error = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .user("Hello")
+            .prefill("{\n")
+            .max_tokens(100)
+            .build()
+            .unwrap_err();
+        assert_eq!(error, BuildError::PrefillTrailingWhitespace);
+    }
+
+    #[test]
+    fn test_empty_metadata_is_omitted() {
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Hello".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(10)
+            .metadata(std::collections::HashMap::new())
+            .build()
+            .expect("Failed to build request");
+
+        assert_request_eq!(
+            request,
+            serde_json::json!({
+                "model": "claude-3-haiku-20240307",
+                "messages": [{"role": "user", "content": [{"type": "text", "text": "Hello"}]}],
+                "max_tokens": 10
+            })
+        );
+    }
+
+    #[test]
+    fn test_omit_model_from_body() -> Result<()> {
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Hello".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(10)
+            .omit_model(true)
+            .build()
+            .expect("Failed to build request");
+
+        let body = serde_json::to_value(&request)?;
+
+        assert_eq!(request.model, Model::Haiku3);
+        assert!(body.get("model").is_none());
+        assert_eq!(body["messages"][0]["content"][0]["text"], "Hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_create_struct_tool() {
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        struct Calculator {
+            operation: String,
+            operands: Vec<f64>,
+        }
+
+        impl ToolBuilder for Calculator {
+            fn name() -> &'static str {
+                "calculator"
+            }
+
+            fn description() -> Option<&'static str> {
+                Some("A simple calculator")
+            }
+        }
+
+        let tool = Tool::new::<Calculator>();
+
+        assert_eq!(tool.name, "calculator");
+        assert_eq!(tool.description, Some("A simple calculator".to_string()));
+    }
+
+    #[test]
+    fn test_check_sample() {
+        #[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+        struct SuperBowl {
+            year: u16,
+            winner: String,
+            loser: String,
+        }
+
+        impl ToolBuilder for SuperBowl {
+            fn name() -> &'static str {
+                "extract_super_bowl_info"
+            }
+
+            fn description() -> Option<&'static str> {
+                Some("Extract Super Bowl information from text")
+            }
+        }
+
+        let tool = Tool::new::<SuperBowl>();
+
+        let super_bowl: SuperBowl = tool
+            .check_sample(
+                json!({"year": 1982, "winner": "Green Bay Packers", "loser": "Miami Dolphins"}),
+            )
+            .expect("Sample should be valid");
+        assert_eq!(super_bowl.year, 1982);
+
+        for sample in [
+            json!({"year": 1982, "winner": "Green Bay Packers"}),
+            json!({"year": 1982, "winner": "Green Bay Packers", "loser": "Miami Dolphins", "score": "31-10"}),
+            json!({"year": "1982", "winner": "Green Bay Packers", "loser": "Miami Dolphins"}),
+        ] {
+            assert!(matches!(
+                tool.check_sample::<SuperBowl>(sample),
+                Err(Error::InvalidToolInput { ref name, .. }) if name == "extract_super_bowl_info"
+            ));
+        }
+    }
+
+    #[test]
+    fn test_check_sample_follows_schema() {
+        #[derive(Debug, Deserialize, JsonSchema)]
+        struct Team {
+            #[schemars(length(min = 1))]
+            name: String,
+        }
+
+        #[derive(Debug, Deserialize, JsonSchema)]
+        struct Review {
+            #[schemars(range(min = 1, max = 5))]
+            stars: u8,
+            teams: Vec<Team>,
+        }
+
+        impl ToolBuilder for Review {
+            fn name() -> &'static str {
+                "review"
+            }
+
+            fn description() -> Option<&'static str> {
+                None
+            }
+        }
+
+        let tool = Tool::new::<Review>();
+        let review: Review = tool
+            .check_sample(json!({"stars": 4, "teams": [{"name": "A"}]}))
+            .expect("Sample should be valid");
+        assert_eq!((review.stars, review.teams[0].name.as_str()), (4, "A"));
+
+        // Both samples deserialize into `Review`, but break the bounds in its schema.
+        for (sample, path) in [
+            (json!({"stars": 9, "teams": []}), "/stars"),
+            (
+                json!({"stars": 4, "teams": [{"name": ""}]}),
+                "/teams/0/name",
+            ),
+        ] {
+            assert!(serde_json::from_value::<Review>(sample.clone()).is_ok());
+            match tool.check_sample::<Review>(sample) {
+                Err(Error::InvalidToolInput { source, .. }) => {
+                    assert!(source.to_string().starts_with(path), "{}", source)
+                }
+                other => panic!("Expected InvalidToolInput, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_tool_description_from_doc_comment() {
+        /// Look up the current exchange rate between two currencies.
+        #[derive(JsonSchema)]
+        #[allow(dead_code)]
+        struct GetExchangeRate {
+            base: String,
+            quote: String,
+        }
+
+        impl ToolBuilder for GetExchangeRate {
+            fn name() -> &'static str {
+                "get_exchange_rate"
+            }
+
+            fn description() -> Option<&'static str> {
+                None
+            }
+        }
+
+        let tool = Tool::new::<GetExchangeRate>();
+
+        assert_eq!(
+            tool.description.as_deref(),
+            Some("Look up the current exchange rate between two currencies.")
+        );
+        assert_eq!(
+            Tool::new::<GetStockPrice>().description.as_deref(),
+            Some("Get the current stock price for a given ticker symbol.")
+        );
+    }
+
+    #[test]
+    fn test_nested_tool_schema_keeps_definitions() -> Result<()> {
+        #[derive(JsonSchema)]
+        #[allow(dead_code)]
+        struct Order {
+            customer: Customer,
+        }
+
+        #[derive(JsonSchema)]
+        #[allow(dead_code)]
+        struct Customer {
+            name: String,
+            address: Address,
+        }
+
+        #[derive(JsonSchema)]
+        #[allow(dead_code)]
+        struct Address {
+            city: String,
+        }
+
+        impl ToolBuilder for Order {
+            fn name() -> &'static str {
+                "place_order"
+            }
+
+            fn description() -> Option<&'static str> {
+                None
+            }
+        }
+
+        let schema = serde_json::to_value(Tool::new::<Order>().input_schema)?;
+
+        assert_eq!(schema["additionalProperties"], false);
+        assert_eq!(schema["properties"]["customer"]["$ref"], "#/$defs/Customer");
+        assert_eq!(
+            schema["$defs"]["Customer"]["properties"]["address"]["$ref"],
+            "#/$defs/Address"
+        );
+        assert_eq!(
+            schema["$defs"]["Address"]["properties"]["city"]["type"],
+            "string"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_newtype_tools() -> Result<()> {
+        /// A block of code to review.
+        #[derive(JsonSchema)]
+        #[allow(dead_code)]
+        struct CodeBlock(String);
+
+        impl ToolBuilder for CodeBlock {
+            fn name() -> &'static str {
+                "review_code"
+            }
+
+            fn description() -> Option<&'static str> {
+                None
+            }
+        }
+
+        #[derive(JsonSchema)]
+        #[allow(dead_code)]
+        struct StockQuery(GetStockPrice);
+
+        impl ToolBuilder for StockQuery {
+            fn name() -> &'static str {
+                "query_stock"
+            }
+
+            fn description() -> Option<&'static str> {
+                None
+            }
+        }
+
+        assert!(matches!(
+            Tool::try_new::<CodeBlock>(),
+            Err(Error::UnsupportedToolSchema(name)) if name == "review_code"
+        ));
+
+        let tool = Tool::try_new::<StockQuery>()?;
+        let schema = serde_json::to_value(&tool.input_schema)?;
+        assert_eq!(schema["type"], "object");
+        assert_eq!(schema["properties"]["ticker"]["type"], "string");
+        assert_eq!(schema["required"], serde_json::json!(["ticker"]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_tools_to_request() {
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        struct Calculator {
+            operation: String,
+            operands: Vec<f64>,
+        }
+
+        impl ToolBuilder for Calculator {
+            fn name() -> &'static str {
+                "calculator"
+            }
+
+            fn description() -> Option<&'static str> {
+                Some("A simple calculator")
+            }
+        }
+
+        let tool = Tool::new::<Calculator>();
+
+        let request = ClaudeRequest::builder()
+            .model(Model::Opus3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Hello".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(10)
+            .tools(vec![tool])
+            .build();
 
-        let response = client
-            .post("https://api.anthropic.com/v1/messages")
-            .headers(headers)
-            .body(body)
-            .send()
-            .await?;
+        assert!(request.is_ok());
+        assert!(request.unwrap().tools.is_some());
+    }
 
-        let status = response.status();
+    #[test]
+    fn test_tools_macro() -> Result<()> {
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        struct GetExchangeRate {
+            from: String,
+            to: String,
+        }
 
-        let text = response
-            .text()
-            .await
-            .context("Failed to get response text")?;
+        impl ToolBuilder for GetExchangeRate {
+            fn name() -> &'static str {
+                "get_exchange_rate"
+            }
 
-        if status.is_success() {
-            // let claude_response: ClaudeResponse =
-            //     serde_json::from_str(&text).context("Failed to deserialize ClaudeResponse")?;
-            Ok(text)
-        } else {
-            Err(anyhow::anyhow!(
-                "API request failed with status: {}. Error: {}",
-                status,
-                text
-            ))
+            fn description() -> Option<&'static str> {
+                None
+            }
         }
-    }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use anyhow::Result;
-    use pretty_assertions::assert_eq;
-    use schemars::JsonSchema;
-
-    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
-    struct GetStockPrice {
-        ticker: String,
-    }
+        let manual = vec![Tool::new::<GetStockPrice>(), Tool::new::<GetExchangeRate>()];
 
-    impl ToolBuilder for GetStockPrice {
-        fn name() -> &'static str {
-            "get_stock_price"
-        }
+        assert_eq!(
+            serde_json::to_value(tools![GetStockPrice, GetExchangeRate])?,
+            serde_json::to_value(manual)?
+        );
 
-        fn description() -> Option<&'static str> {
-            Some("Get the current stock price for a given ticker symbol.")
-        }
+        Ok(())
     }
 
     #[test]
-    fn test_request_builder() {
-        let stock_price_tool = Tool::new::<GetStockPrice>();
-
+    fn test_force_single_tool() {
         let request = ClaudeRequest::builder()
             .model(Model::Opus3)
             .add_message(
                 Role::User,
                 vec![ContentType::Text {
                     text: "What's the current stock price of Apple?".to_string(),
+                    cache_control: None,
                 }],
             )
-            .max_tokens(100)
-            .temperature(0.7)
-            .tools(vec![stock_price_tool])
-            .tool_choice(ToolChoice::Auto {
-                disable_parallel_tool_use: Some(true),
-            })
+            .max_tokens(10)
+            .tools(vec![Tool::new::<GetStockPrice>()])
+            .force_single_tool()
             .build()
             .expect("Failed to build request");
 
-        assert_eq!(request.model, Model::Opus3);
-        assert_eq!(request.max_tokens, 100);
-        assert_eq!(request.temperature, Some(0.7));
-        assert!(request.tools.is_some());
         assert!(matches!(
             request.tool_choice,
-            Some(ToolChoice::Auto {
-                disable_parallel_tool_use: Some(true)
-            })
+            Some(ToolChoice::Specific { ref name, .. }) if name == "get_stock_price"
         ));
-    }
 
-    #[test]
-    fn test_minimal_valid_request() {
-        let request = ClaudeRequest::builder()
+        let no_tools = ClaudeRequest::builder()
             .model(Model::Opus3)
             .add_message(
                 Role::User,
                 vec![ContentType::Text {
                     text: "Hello".to_string(),
+                    cache_control: None,
                 }],
             )
             .max_tokens(10)
+            .force_single_tool()
             .build();
 
-        assert!(request.is_ok());
+        assert_eq!(no_tools.unwrap_err(), BuildError::NotSingleTool(0));
     }
 
-    #[test]
-    fn test_request_with_all_params() {
-        let request = ClaudeRequest::builder()
-            .model(Model::Haiku3)
+    fn tool_use_turn() -> ClaudeRequestBuilder {
+        ClaudeRequest::builder()
+            .model(Model::Opus3)
             .add_message(
                 Role::User,
                 vec![ContentType::Text {
-                    text: "Hello".to_string(),
+                    text: "What's the current stock price of Apple?".to_string(),
+                    cache_control: None,
                 }],
             )
+            .add_message(
+                Role::Assistant,
+                vec![ContentType::ToolUse(ToolUse::new(
+                    "toolu_01",
+                    "get_stock_price",
+                    serde_json::json!({"ticker": "AAPL"}),
+                ))],
+            )
             .max_tokens(10)
-            .temperature(0.7)
-            .top_k(10)
-            .top_p(0.9)
-            .stream(true)
-            .system("You are a helpful assistant.")
-            .stop_sequences(vec!["STOP".to_string()])
-            .metadata(std::collections::HashMap::new())
-            .build();
+            .tools(vec![Tool::new::<GetStockPrice>()])
+    }
 
-        assert!(request.is_ok());
+    fn tool_result(tool_use_id: &str) -> ToolResult {
+        ToolResult::new(tool_use_id, "$227.52")
     }
 
     #[test]
-    fn test_multiple_messages() {
+    fn test_tool_result_turn() {
+        let request = tool_use_turn()
+            .tool_result_turn(vec![tool_result("toolu_01")])
+            .build()
+            .expect("Failed to build request");
+
+        let turn = request.messages.last().unwrap();
+        assert_eq!(turn.role, Role::User);
+        assert!(matches!(
+            turn.content.as_slice(),
+            [ContentType::ToolResult(result)] if result.tool_use_id == "toolu_01"
+        ));
+    }
+
+    #[test]
+    fn test_add_tool_use_and_results() {
         let request = ClaudeRequest::builder()
-            .model(Model::Sonnet35)
+            .model(Model::Opus3)
             .add_message(
                 Role::User,
                 vec![ContentType::Text {
-                    text: "Hello".to_string(),
+                    text: "Compare Apple and Microsoft".to_string(),
+                    cache_control: None,
                 }],
             )
-            .add_message(
-                Role::Assistant,
-                vec![ContentType::Text {
-                    text: "Hi there!".to_string(),
-                }],
+            .add_tool_use(
+                "toolu_01",
+                "get_stock_price",
+                serde_json::json!({"ticker": "AAPL"}),
             )
-            .add_message(
-                Role::User,
-                vec![ContentType::Text {
-                    text: "How are you?".to_string(),
-                }],
+            .add_tool_use(
+                "toolu_02",
+                "get_stock_price",
+                serde_json::json!({"ticker": "MSFT"}),
             )
+            .add_tool_result("toolu_01", "$227.52")
+            .add_tool_result("toolu_02", "$415.10")
             .max_tokens(10)
-            .build();
+            .build()
+            .expect("Failed to build request");
 
-        assert!(request.is_ok());
-        assert_eq!(request.unwrap().messages.len(), 3);
+        assert_request_eq!(
+            request,
+            serde_json::json!({
+                "model": "claude-3-opus-20240229",
+                "max_tokens": 10,
+                "messages": [
+                    {
+                        "role": "user",
+                        "content": [{"type": "text", "text": "Compare Apple and Microsoft"}]
+                    },
+                    {
+                        "role": "assistant",
+                        "content": [
+                            {
+                                "type": "tool_use",
+                                "id": "toolu_01",
+                                "name": "get_stock_price",
+                                "input": {"ticker": "AAPL"}
+                            },
+                            {
+                                "type": "tool_use",
+                                "id": "toolu_02",
+                                "name": "get_stock_price",
+                                "input": {"ticker": "MSFT"}
+                            }
+                        ]
+                    },
+                    {
+                        "role": "user",
+                        "content": [
+                            {"type": "tool_result", "tool_use_id": "toolu_01", "content": "$227.52"},
+                            {"type": "tool_result", "tool_use_id": "toolu_02", "content": "$415.10"}
+                        ]
+                    }
+                ]
+            })
+        );
     }
 
     #[test]
-    fn test_metadata() {
-        let mut metadata = std::collections::HashMap::new();
-        metadata.insert("key".to_string(), "value".to_string());
+    fn test_tool_result_content_forms() -> Result<()> {
+        let text = ContentType::ToolResult(ToolResult::new("toolu_01", "$227.52"));
+        assert_eq!(
+            serde_json::to_value(&text)?,
+            serde_json::json!({"type": "tool_result", "tool_use_id": "toolu_01", "content": "$227.52"})
+        );
 
-        let request = ClaudeRequest::builder()
-            .model(Model::Haiku3)
+        let blocks = ContentType::ToolResult(ToolResult::new(
+            "toolu_01",
+            vec![ContentType::Text {
+                text: "$227.52".to_string(),
+                cache_control: None,
+            }],
+        ));
+        assert_eq!(
+            serde_json::to_value(&blocks)?,
+            serde_json::json!({
+                "type": "tool_result",
+                "tool_use_id": "toolu_01",
+                "content": [{"type": "text", "text": "$227.52"}]
+            })
+        );
+
+        let error = ContentType::ToolResult(ToolResult::error("toolu_01", "Unknown ticker"));
+        assert_eq!(
+            serde_json::to_value(&error)?,
+            serde_json::json!({
+                "type": "tool_result",
+                "tool_use_id": "toolu_01",
+                "content": "Unknown ticker",
+                "is_error": true
+            })
+        );
+
+        let parsed: ContentType = serde_json::from_value(serde_json::json!({
+            "type": "tool_result",
+            "tool_use_id": "toolu_01",
+            "content": [{"type": "text", "text": "$227.52"}],
+            "is_error": true
+        }))?;
+        assert!(matches!(
+            parsed,
+            ContentType::ToolResult(ToolResult {
+                content: ToolResultContent::Blocks(ref blocks),
+                is_error: true,
+                ..
+            }) if blocks.len() == 1
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tool_result_turn_with_mismatched_id() {
+        let builder = tool_use_turn().tool_result_turn(vec![tool_result("toolu_02")]);
+
+        assert_eq!(
+            builder.validate_now(),
+            Err(BuildError::UnmatchedToolResult("toolu_02".to_string()))
+        );
+        assert_eq!(
+            builder.build().unwrap_err(),
+            BuildError::UnmatchedToolResult("toolu_02".to_string())
+        );
+    }
+
+    #[test]
+    fn test_web_search_results() -> Result<()> {
+        let response: ClaudeResponse = serde_json::from_value(message_json(
+            serde_json::json!([
+                {"type": "text", "text": "Let me search for that."},
+                {
+                    "type": "web_search_tool_result",
+                    "tool_use_id": "srvtoolu_01",
+                    "content": [{
+                        "type": "web_search_result",
+                        "url": "https://en.wikipedia.org/wiki/Super_Bowl_XVI",
+                        "title": "Super Bowl XVI - Wikipedia",
+                        "encrypted_content": "EqgfCioIARgBIiQ3",
+                        "page_age": "April 30, 2025"
+                    }]
+                },
+                {
+                    "type": "web_search_tool_result",
+                    "tool_use_id": "srvtoolu_02",
+                    "content": {"type": "web_search_tool_result_error", "error_code": "unavailable"}
+                }
+            ]),
+            "end_turn",
+        ))?;
+
+        assert_eq!(
+            response.web_search_results(),
+            vec![WebSearchResult {
+                title: "Super Bowl XVI - Wikipedia".to_string(),
+                url: "https://en.wikipedia.org/wiki/Super_Bowl_XVI".to_string(),
+                page_age: Some("April 30, 2025".to_string()),
+                encrypted_content: "EqgfCioIARgBIiQ3".to_string(),
+            }]
+        );
+
+        Ok(())
+    }
+
+    fn tool_choice_request(
+        tools: Option<Vec<Tool>>,
+        tool_choice: ToolChoice,
+    ) -> Result<ClaudeRequest, BuildError> {
+        let mut builder = ClaudeRequest::builder()
+            .model(Model::Opus3)
             .add_message(
                 Role::User,
                 vec![ContentType::Text {
-                    text: "Hello".to_string(),
+                    text: "What's the current stock price of Apple?".to_string(),
+                    cache_control: None,
                 }],
             )
             .max_tokens(10)
-            .metadata(metadata.clone())
-            .build();
-
-        assert!(request.is_ok());
-        assert_eq!(request.unwrap().metadata, Some(metadata));
+            .tool_choice(tool_choice);
+        if let Some(tools) = tools {
+            builder = builder.tools(tools);
+        }
+        builder.build()
     }
 
     #[test]
-    fn test_create_struct_tool() {
-        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
-        struct Calculator {
-            operation: String,
-            operands: Vec<f64>,
-        }
+    fn test_tool_choice_must_name_a_tool() {
+        let specific = |name: &str| ToolChoice::Specific {
+            name: name.to_string(),
+            disable_parallel_tool_use: None,
+        };
 
-        impl ToolBuilder for Calculator {
-            fn name() -> &'static str {
-                "calculator"
-            }
+        assert!(tool_choice_request(
+            Some(vec![Tool::new::<GetStockPrice>()]),
+            specific("get_stock_price")
+        )
+        .is_ok());
+        assert_eq!(
+            tool_choice_request(
+                Some(vec![Tool::new::<GetStockPrice>()]),
+                specific("get_stock_prise")
+            )
+            .unwrap_err(),
+            BuildError::UnknownToolChoice("get_stock_prise".to_string())
+        );
+        assert_eq!(
+            tool_choice_request(None, specific("get_stock_price")).unwrap_err(),
+            BuildError::UnknownToolChoice("get_stock_price".to_string())
+        );
+    }
 
-            fn description() -> Option<&'static str> {
-                Some("A simple calculator")
+    #[test]
+    fn test_tool_choice_without_tools() {
+        assert!(tool_choice_request(None, ToolChoice::None).is_ok());
+        assert!(tool_choice_request(
+            None,
+            ToolChoice::Auto {
+                disable_parallel_tool_use: None
             }
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn test_typed_tool_use() -> Result<()> {
+        #[derive(Debug, Deserialize)]
+        struct SuperBowl {
+            year: u16,
+            winner: String,
+            loser: String,
         }
 
-        let tool = Tool::new::<Calculator>();
+        let tool_use: ToolUse = serde_json::from_value(serde_json::json!({
+            "id": "toolu_01",
+            "name": "extract_super_bowl_info",
+            "input": {"year": 1982, "winner": "Green Bay Packers", "loser": "Miami Dolphins"}
+        }))?;
 
-        assert_eq!(tool.name, "calculator");
-        assert_eq!(tool.description, Some("A simple calculator".to_string()));
+        let typed = tool_use.typed::<SuperBowl>()?;
+
+        assert_eq!(typed.id, "toolu_01");
+        assert_eq!(typed.name, "extract_super_bowl_info");
+        assert_eq!(typed.input.year, 1982);
+        assert_eq!(typed.input.winner, "Green Bay Packers");
+        assert_eq!(typed.input.loser, "Miami Dolphins");
+
+        Ok(())
     }
 
     #[test]
-    fn test_add_tools_to_request() {
-        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
-        struct Calculator {
-            operation: String,
-            operands: Vec<f64>,
+    fn test_response_text_and_tool_uses() -> Result<()> {
+        let response: ClaudeResponse = serde_json::from_value(message_json(
+            serde_json::json!([
+                {"type": "text", "text": "Let me check "},
+                {
+                    "type": "server_tool_use",
+                    "id": "srvtoolu_01",
+                    "name": "web_search",
+                    "input": {"query": "AAPL news"}
+                },
+                {"type": "text", "text": "both prices."},
+                {"type": "tool_use", "id": "toolu_01", "name": "get_stock_price", "input": {"ticker": "AAPL"}},
+                {"type": "tool_use", "id": "toolu_02", "name": "get_stock_price", "input": {"ticker": "MSFT"}}
+            ]),
+            "tool_use",
+        ))?;
+
+        assert_eq!(response.text(), "Let me check both prices.");
+
+        let ids: Vec<&str> = response
+            .tool_uses()
+            .iter()
+            .map(|tool_use| tool_use.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["toolu_01", "toolu_02"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_duplicate_tool_use_ids() -> Result<()> {
+        let response: ClaudeResponse = serde_json::from_value(message_json(
+            serde_json::json!([
+                {"type": "tool_use", "id": "toolu_01", "name": "get_stock_price", "input": {"ticker": "AAPL"}},
+                {"type": "tool_use", "id": "toolu_01", "name": "get_stock_price", "input": {"ticker": "MSFT"}}
+            ]),
+            "tool_use",
+        ))?;
+
+        assert!(matches!(
+            response.unique_tool_uses(),
+            Err(Error::DuplicateToolUseId(id)) if id == "toolu_01"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_assistant_message_continues_conversation() -> Result<()> {
+        let response: ClaudeResponse = serde_json::from_value(message_json(
+            serde_json::json!([
+                {"type": "thinking", "thinking": "I need the price.", "signature": "EuYBCkQYAiJA"},
+                {"type": "text", "text": "Let me look that up."},
+                {"type": "tool_use", "id": "toolu_01", "name": "get_stock_price", "input": {"ticker": "AAPL"}}
+            ]),
+            "tool_use",
+        ))?;
+
+        let message = response.assistant_message();
+        assert_eq!(message.role, Role::Assistant);
+        assert!(matches!(
+            &message.content[..],
+            [
+                ContentType::Thinking { .. },
+                ContentType::Text { .. },
+                ContentType::ToolUse(_)
+            ]
+        ));
+
+        let request = ClaudeRequest::builder()
+            .model(Model::Sonnet37)
+            .user("What is Apple trading at?")
+            .extend_messages([message])
+            .tool_result_turn(vec![ToolResult::new("toolu_01", "$227.48")])
+            .max_tokens(1024)
+            .build()
+            .expect("Failed to build request");
+
+        let roles: Vec<&Role> = request.messages.iter().map(|m| &m.role).collect();
+        assert_eq!(roles, vec![&Role::User, &Role::Assistant, &Role::User]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extract_from_response() -> Result<()> {
+        #[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+        struct SuperBowl {
+            year: u16,
+            winner: String,
+            loser: String,
         }
 
-        impl ToolBuilder for Calculator {
+        impl ToolBuilder for SuperBowl {
             fn name() -> &'static str {
-                "calculator"
+                "extract_super_bowl_info"
             }
 
             fn description() -> Option<&'static str> {
-                Some("A simple calculator")
+                None
             }
         }
 
-        let tool = Tool::new::<Calculator>();
+        let response = |input: Value| -> Result<ClaudeResponse> {
+            Ok(serde_json::from_value(message_json(
+                serde_json::json!([{
+                    "type": "tool_use",
+                    "id": "toolu_01",
+                    "name": "extract_super_bowl_info",
+                    "input": input
+                }]),
+                "tool_use",
+            ))?)
+        };
 
-        let request = ClaudeRequest::builder()
-            .model(Model::Opus3)
-            .add_message(
-                Role::User,
-                vec![ContentType::Text {
-                    text: "Hello".to_string(),
-                }],
-            )
-            .max_tokens(10)
-            .tools(vec![tool])
-            .build();
+        let super_bowl: SuperBowl = response(serde_json::json!({
+            "year": 1982,
+            "winner": "Green Bay Packers",
+            "loser": "Miami Dolphins"
+        }))?
+        .extract()?;
+        assert_eq!(
+            super_bowl,
+            SuperBowl {
+                year: 1982,
+                winner: "Green Bay Packers".to_string(),
+                loser: "Miami Dolphins".to_string(),
+            }
+        );
 
-        assert!(request.is_ok());
-        assert!(request.unwrap().tools.is_some());
+        let err = response(serde_json::json!({"year": "1982"}))?
+            .extract::<SuperBowl>()
+            .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InvalidToolInput { ref name, .. } if name == "extract_super_bowl_info"
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_match_tool() -> Result<()> {
+        let response: ClaudeResponse = serde_json::from_value(message_json(
+            serde_json::json!([
+                {"type": "text", "text": "Let me look that up."},
+                {
+                    "type": "tool_use",
+                    "id": "toolu_01",
+                    "name": "get_stock_price",
+                    "input": {"ticker": "AAPL"}
+                }
+            ]),
+            "tool_use",
+        ))?;
+        let tools = tools![GetStockPrice];
+
+        let (tool, tool_use) = response.match_tool(&tools).expect("tool should match");
+        assert_eq!(tool.name, "get_stock_price");
+        assert_eq!(tool_use.id, "toolu_01");
+
+        assert!(response.match_tool(&[]).is_none());
+
+        Ok(())
     }
 
     #[test]
@@ -668,6 +3913,7 @@ mod tests {
                 Role::User,
                 vec![ContentType::Text {
                     text: "Hello".to_string(),
+                    cache_control: None,
                 }],
             )
             .max_tokens(10)
@@ -686,7 +3932,65 @@ mod tests {
     }
 
     #[test]
-    fn test_tool_use_request_body_valid() -> Result<()> {
+    fn test_tool_choice_round_trip() -> Result<()> {
+        let choices = [
+            (ToolChoice::None, serde_json::json!({})),
+            (
+                ToolChoice::Auto {
+                    disable_parallel_tool_use: None,
+                },
+                serde_json::json!({"type": "auto"}),
+            ),
+            (
+                ToolChoice::Any {
+                    disable_parallel_tool_use: Some(true),
+                },
+                serde_json::json!({"type": "any", "disable_parallel_tool_use": true}),
+            ),
+            (
+                ToolChoice::Specific {
+                    name: "get_stock_price".to_string(),
+                    disable_parallel_tool_use: Some(false),
+                },
+                serde_json::json!({
+                    "type": "tool",
+                    "name": "get_stock_price",
+                    "disable_parallel_tool_use": false
+                }),
+            ),
+        ];
+
+        for (choice, json) in choices {
+            assert_eq!(serde_json::to_value(&choice)?, json);
+            assert_eq!(serde_json::from_value::<ToolChoice>(json)?, choice);
+        }
+        assert!(serde_json::from_value::<ToolChoice>(serde_json::json!({"type": "tool"})).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_tool_use_request_body_valid() {
+        #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+        struct SuperBowl {
+            year: u16,
+            winner: String,
+            loser: String,
+            winner_score: u8,
+            loser_score: u8,
+            total_points_scored: Option<u8>,
+        }
+
+        impl ToolBuilder for SuperBowl {
+            fn name() -> &'static str {
+                "extract_super_bowl_info"
+            }
+
+            fn description() -> Option<&'static str> {
+                Some("Extract Super Bowl information from text")
+            }
+        }
+
         let chat = ClaudeRequest::builder()
             .model(Model::Sonnet35)
             .max_tokens(200)
@@ -694,6 +3998,7 @@ mod tests {
                 Role::Assistant,
                 vec![ContentType::Text {
                     text: "You're an NFL expert extract the game info.".to_string(),
+                    cache_control: None,
                 }],
             )
             .add_message(
@@ -701,17 +4006,27 @@ mod tests {
                 vec![ContentType::Text {
                 text: "The Green Bay Packers beat the Miami Dolphins in the 1982 Super Bowl 31-10."
                     .to_string(),
+                cache_control: None,
             }],
             )
-            .build();
+            .tools(vec![Tool::new::<SuperBowl>()])
+            .tool_choice(ToolChoice::Specific {
+                name: "extract_super_bowl_info".to_string(),
+                disable_parallel_tool_use: Some(false),
+            })
+            .build()
+            .expect("Failed to build request");
 
         let expected = serde_json::json!({
-             "model": "claude-3-opus-20240229",
+             "model": "claude-3-5-sonnet-20240620",
              "messages": [
                {
                  "role": "assistant",
                  "content": [
-                   "You're an NFL expert extract the game info."
+                   {
+                     "type": "text",
+                     "text": "You're an NFL expert extract the game info."
+                   }
                  ]
                },
                {
@@ -765,7 +4080,8 @@ mod tests {
                        "winner",
                        "winner_score",
                        "year"
-                     ]
+                     ],
+                     "additionalProperties": false
                    }
                  }
               ],
@@ -776,8 +4092,6 @@ mod tests {
             }
         });
 
-        // let expected: serde_json::Value = serde_json::from_str(expected)?;
-
-        Ok(())
+        assert_request_eq!(chat, expected);
     }
 }
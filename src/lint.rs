@@ -0,0 +1,193 @@
+//! Advisory checks for requests that are likely to go wrong.
+
+use crate::{ClaudeRequest, ContentType, Model, System, ToolResultContent};
+use std::fmt;
+
+/// Rough number of characters per token, used to estimate input size without a tokenizer.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Estimated input size, in tokens, above which a request is flagged as large.
+const LARGE_INPUT_TOKENS: usize = 100_000;
+
+/// Represents a warning about a request that can still be sent.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lint {
+    /// The messages and system prompt are estimated to use a large share of the context window.
+    LargeInput { estimated_tokens: usize },
+    /// `max_tokens` is close to, or above, the most the model can generate.
+    MaxTokensNearLimit { max_tokens: u32, limit: u32 },
+    /// Tools are provided without a system prompt explaining when to use them.
+    ToolsWithoutSystem,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Lint::LargeInput { estimated_tokens } => write!(
+                f,
+                "Input is roughly {} tokens, which may exceed the context window",
+                estimated_tokens
+            ),
+            Lint::MaxTokensNearLimit { max_tokens, limit } => write!(
+                f,
+                "max_tokens is {} but the model generates at most {} tokens",
+                max_tokens, limit
+            ),
+            Lint::ToolsWithoutSystem => write!(f, "Tools are provided without a system prompt"),
+        }
+    }
+}
+
+impl ClaudeRequest {
+    /// Checks the request for likely problems without blocking it from being sent.
+    pub fn lint(&self) -> Vec<Lint> {
+        let mut lints = Vec::new();
+
+        let estimated_tokens = self.estimated_input_tokens();
+        if estimated_tokens > LARGE_INPUT_TOKENS {
+            lints.push(Lint::LargeInput { estimated_tokens });
+        }
+
+        if let Some(limit) = max_output_tokens(&self.model) {
+            if self.max_tokens >= limit / 10 * 9 {
+                lints.push(Lint::MaxTokensNearLimit {
+                    max_tokens: self.max_tokens,
+                    limit,
+                });
+            }
+        }
+
+        if self.tools.as_ref().is_some_and(|tools| !tools.is_empty()) && self.system.is_none() {
+            lints.push(Lint::ToolsWithoutSystem);
+        }
+
+        lints
+    }
+
+    /// Roughly estimates the input tokens of the request from its length.
+    pub(crate) fn estimated_input_tokens(&self) -> usize {
+        self.input_chars() / CHARS_PER_TOKEN
+    }
+
+    /// Counts the characters of text sent to the model.
+    fn input_chars(&self) -> usize {
+        let system = match &self.system {
+            Some(System::Text(text)) => text.len(),
+            _ => 0,
+        };
+        let blocks: usize = self
+            .messages
+            .iter()
+            .flat_map(|message| &message.content)
+            .chain(self.system.iter().flat_map(System::blocks))
+            .map(content_chars)
+            .sum();
+
+        system + blocks
+    }
+}
+
+/// Counts the characters of text in a content block.
+fn content_chars(content: &ContentType) -> usize {
+    match content {
+        ContentType::Text { text, .. } => text.len(),
+        ContentType::ToolResult(result) => match &result.content {
+            ToolResultContent::Text(text) => text.len(),
+            ToolResultContent::Blocks(blocks) => blocks.iter().map(content_chars).sum(),
+        },
+        ContentType::ToolUse(tool_use) => tool_use.input.to_string().len(),
+        _ => 0,
+    }
+}
+
+/// Returns the most tokens the model can generate in one response, if known.
+fn max_output_tokens(model: &Model) -> Option<u32> {
+    match model {
+        Model::Opus3 | Model::Opus3Latest | Model::Sonnet3 | Model::Haiku3 => Some(4096),
+        Model::Sonnet35
+        | Model::Sonnet35V2
+        | Model::Sonnet35Latest
+        | Model::Haiku35
+        | Model::Haiku35Latest => Some(8192),
+        Model::Opus4 | Model::Opus41 => Some(32000),
+        Model::Sonnet37
+        | Model::Sonnet37Latest
+        | Model::Sonnet4
+        | Model::Sonnet45
+        | Model::Haiku45
+        | Model::Opus45 => Some(64000),
+        Model::Other(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Role, Tool, ToolBuilder};
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    struct Issue {
+        title: String,
+    }
+
+    impl ToolBuilder for Issue {
+        fn name() -> &'static str {
+            "record_issue"
+        }
+
+        fn description() -> Option<&'static str> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_lint_oversized_request() {
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "fn main() {}\n".repeat(40_000),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(4000)
+            .tools(vec![Tool::new::<Issue>()])
+            .build()
+            .expect("Failed to build request");
+
+        assert_eq!(
+            request.lint(),
+            vec![
+                Lint::LargeInput {
+                    estimated_tokens: 130_000
+                },
+                Lint::MaxTokensNearLimit {
+                    max_tokens: 4000,
+                    limit: 4096
+                },
+                Lint::ToolsWithoutSystem,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint_reasonable_request() {
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .add_message(
+                Role::User,
+                vec![ContentType::Text {
+                    text: "Hello".to_string(),
+                    cache_control: None,
+                }],
+            )
+            .max_tokens(1024)
+            .build()
+            .expect("Failed to build request");
+
+        assert!(request.lint().is_empty());
+    }
+}
@@ -0,0 +1,156 @@
+//! Building image and document content from files, raw bytes and URLs.
+
+use crate::{ContentType, DocumentSource, Error, ImageSource, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::path::Path;
+
+/// Image media types accepted by the API.
+const IMAGE_MEDIA_TYPES: [&str; 4] = ["image/png", "image/jpeg", "image/gif", "image/webp"];
+
+const PDF_MEDIA_TYPE: &str = "application/pdf";
+
+impl ContentType {
+    /// Reads an image file into an image content block.
+    ///
+    /// The media type is detected from the file's contents, falling back to its extension.
+    /// Formats other than PNG, JPEG, GIF and WebP are rejected.
+    pub fn image_from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let bytes = std::fs::read(path)?;
+
+        let media_type = sniff_image(&bytes)
+            .or_else(|| image_type_from_extension(path))
+            .ok_or_else(|| Error::UnsupportedMediaType(path.display().to_string()))?;
+
+        Self::image_from_bytes(&bytes, media_type)
+    }
+
+    /// Encodes raw image bytes of the given media type into an image content block.
+    pub fn image_from_bytes(bytes: &[u8], media_type: &str) -> Result<Self> {
+        if !IMAGE_MEDIA_TYPES.contains(&media_type) {
+            return Err(Error::UnsupportedMediaType(media_type.to_string()));
+        }
+
+        Ok(ContentType::Image {
+            source: ImageSource::Base64 {
+                media_type: media_type.to_string(),
+                data: STANDARD.encode(bytes),
+            },
+        })
+    }
+
+    /// Creates an image content block that the API fetches from `url`.
+    pub fn image_from_url(url: impl Into<String>) -> Self {
+        ContentType::Image {
+            source: ImageSource::Url { url: url.into() },
+        }
+    }
+
+    /// Reads a PDF file into a document content block.
+    pub fn document_from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+
+        Self::document_from_bytes(&bytes)
+    }
+
+    /// Encodes the bytes of a PDF into a document content block.
+    ///
+    /// Errors if the bytes do not start with the PDF signature.
+    pub fn document_from_bytes(bytes: &[u8]) -> Result<Self> {
+        if !bytes.starts_with(b"%PDF-") {
+            return Err(Error::UnsupportedMediaType(
+                "document is not a PDF".to_string(),
+            ));
+        }
+
+        Ok(ContentType::Document {
+            source: DocumentSource::Base64 {
+                media_type: PDF_MEDIA_TYPE.to_string(),
+                data: STANDARD.encode(bytes),
+            },
+            cache_control: None,
+        })
+    }
+}
+
+/// Detects the image media type from the file signature.
+fn sniff_image(bytes: &[u8]) -> Option<&'static str> {
+    if bytes.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if bytes.starts_with(&[0xFF, 0xD8, 0xFF]) {
+        Some("image/jpeg")
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+fn image_type_from_extension(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+    match extension.as_str() {
+        "png" => Some("image/png"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "gif" => Some("image/gif"),
+        "webp" => Some("image/webp"),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIXEL: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/pixel.png");
+
+    #[test]
+    fn test_image_from_path_encodes_png() -> Result<()> {
+        let image = ContentType::image_from_path(PIXEL)?;
+
+        let ContentType::Image {
+            source: ImageSource::Base64 { media_type, data },
+        } = image
+        else {
+            panic!("Expected a base64 image block");
+        };
+        assert_eq!(media_type, "image/png");
+        assert_eq!(STANDARD.decode(data).unwrap(), std::fs::read(PIXEL)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unsupported_media_type_is_rejected() {
+        let result = ContentType::image_from_bytes(b"%PDF-1.7", "application/pdf");
+
+        assert!(matches!(result, Err(Error::UnsupportedMediaType(_))));
+    }
+
+    #[test]
+    fn test_document_serializes_as_base64_pdf() -> Result<()> {
+        let pdf = b"%PDF-1.7\n%%EOF\n";
+        let document = ContentType::document_from_bytes(pdf)?;
+
+        assert_eq!(
+            serde_json::to_value(&document)?,
+            serde_json::json!({
+                "type": "document",
+                "source": {
+                    "type": "base64",
+                    "media_type": "application/pdf",
+                    "data": STANDARD.encode(pdf)
+                }
+            })
+        );
+        assert!(matches!(
+            ContentType::document_from_bytes(b"not a pdf"),
+            Err(Error::UnsupportedMediaType(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,365 @@
+//! Pacing requests to stay under the account's rate limits.
+
+use crate::clock::Clock;
+use crate::retry::{header, parse_rfc3339, server_now};
+use reqwest::header::HeaderMap;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+use tokio::time::Instant;
+
+/// Controls how fast a [`Client`](crate::Client) sends message requests.
+///
+/// Each limit is a token bucket holding up to a full window's allowance, refilled evenly over
+/// the window. Before a request is sent the client waits until both buckets can cover it: one
+/// request, and the request's estimated input tokens. Clones of a client share its buckets.
+#[derive(Debug, Clone)]
+pub struct RateLimit {
+    /// Requests allowed per `window`.
+    pub requests: Option<u32>,
+    /// Estimated input tokens allowed per `window`.
+    ///
+    /// Tokens are estimated locally from the length of the request, so leave some headroom
+    /// below the account's actual limit.
+    pub tokens: Option<u32>,
+    /// Period the limits apply to, one minute by default.
+    pub window: Duration,
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        RateLimit {
+            requests: None,
+            tokens: None,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Holds back requests while the account is out of requests, as reported by the API.
+///
+/// Unlike [`RateLimit`], which paces requests against limits given up front, a RateLimiter
+/// learns the limit from the `anthropic-ratelimit-requests-remaining` and `-reset` headers of
+/// each response. Requests sent since the last response are counted against what remained,
+/// and once no more than `reserve` are left, further requests wait until the limit resets.
+///
+/// Until the first response arrives nothing is known, so the first requests are not held.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    reserve: u32,
+    state: Mutex<RemainingRequests>,
+}
+
+#[derive(Debug, Default)]
+struct RemainingRequests {
+    remaining: Option<u32>,
+    reset: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// Creates a RateLimiter that waits once no more than `reserve` requests are left.
+    pub fn new(reserve: u32) -> Self {
+        RateLimiter {
+            reserve,
+            ..Self::default()
+        }
+    }
+
+    /// Waits on `clock` until the account has a request to spare, then claims it.
+    pub(crate) async fn acquire(&self, clock: &dyn Clock) {
+        loop {
+            let wait = {
+                let mut state = self.state.lock().expect("Rate limiter lock poisoned");
+                let now = clock.now();
+                if state.reset.is_some_and(|reset| now >= reset) {
+                    *state = RemainingRequests::default();
+                }
+
+                match (state.remaining, state.reset) {
+                    (Some(remaining), Some(reset)) if remaining <= self.reserve => reset - now,
+                    (remaining, _) => {
+                        state.remaining = remaining.map(|remaining| remaining.saturating_sub(1));
+                        return;
+                    }
+                }
+            };
+
+            clock.sleep(wait).await;
+        }
+    }
+
+    /// Updates what is known about the request limit from a response's headers.
+    pub(crate) fn observe(&self, headers: &HeaderMap, clock: &dyn Clock) {
+        let requests = RateLimitInfo::from_headers(headers).requests;
+        let Some(remaining) = requests.remaining else {
+            return;
+        };
+        let reset = requests.reset.map(|reset| {
+            let server_now = server_now(headers, SystemTime::now());
+            clock.now() + reset.duration_since(server_now).unwrap_or_default()
+        });
+
+        let mut state = self.state.lock().expect("Rate limiter lock poisoned");
+        *state = RemainingRequests {
+            remaining: Some(remaining),
+            reset,
+        };
+    }
+}
+
+/// The state of one rate limit, as reported by the API.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LimitStatus {
+    /// Maximum allowed in the current period.
+    pub limit: Option<u32>,
+    /// Amount left before the limit is reached.
+    pub remaining: Option<u32>,
+    /// When the limit is fully replenished, on the server's clock.
+    pub reset: Option<SystemTime>,
+}
+
+/// The account's rate limits, read from the `anthropic-ratelimit-*` headers of a response.
+///
+/// Limits whose headers are missing are left empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateLimitInfo {
+    pub requests: LimitStatus,
+    pub tokens: LimitStatus,
+    pub input_tokens: LimitStatus,
+    pub output_tokens: LimitStatus,
+}
+
+impl RateLimitInfo {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let status = |limit: &str| {
+            let value =
+                |field: &str| header(headers, &format!("anthropic-ratelimit-{}-{}", limit, field));
+            LimitStatus {
+                limit: value("limit").and_then(|value| value.parse().ok()),
+                remaining: value("remaining").and_then(|value| value.parse().ok()),
+                reset: value("reset").and_then(parse_rfc3339),
+            }
+        };
+
+        RateLimitInfo {
+            requests: status("requests"),
+            tokens: status("tokens"),
+            input_tokens: status("input-tokens"),
+            output_tokens: status("output-tokens"),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    per_second: f64,
+}
+
+impl Bucket {
+    fn new(limit: u32, window: Duration) -> Self {
+        let capacity = f64::from(limit.max(1));
+        Bucket {
+            capacity,
+            available: capacity,
+            per_second: capacity / window.as_secs_f64().max(f64::EPSILON),
+        }
+    }
+
+    fn refill(&mut self, elapsed: Duration) {
+        self.available =
+            (self.available + elapsed.as_secs_f64() * self.per_second).min(self.capacity);
+    }
+
+    /// Returns how long until `cost` is available. Costs above the capacity are capped so
+    /// they can still be paid for with a full bucket.
+    fn wait(&self, cost: f64) -> Duration {
+        let missing = cost.min(self.capacity) - self.available;
+        if missing <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(missing / self.per_second)
+        }
+    }
+
+    fn take(&mut self, cost: f64) {
+        self.available -= cost.min(self.capacity);
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    /// When the buckets were last refilled; unset until the first request.
+    updated: Option<Instant>,
+}
+
+/// The shared state of a [`RateLimit`].
+#[derive(Debug)]
+pub(crate) struct Limiter {
+    buckets: Mutex<Buckets>,
+}
+
+impl Limiter {
+    pub(crate) fn new(limit: &RateLimit) -> Self {
+        Limiter {
+            buckets: Mutex::new(Buckets {
+                requests: limit.requests.map(|n| Bucket::new(n, limit.window)),
+                tokens: limit.tokens.map(|n| Bucket::new(n, limit.window)),
+                updated: None,
+            }),
+        }
+    }
+
+    /// Waits on `clock` until a request estimated to use `tokens` input tokens can be sent.
+    pub(crate) async fn acquire(&self, tokens: usize, clock: &dyn Clock) {
+        let cost = tokens as f64;
+        loop {
+            let wait = {
+                let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+                let now = clock.now();
+                let elapsed = buckets
+                    .updated
+                    .map_or(Duration::ZERO, |updated| now - updated);
+                buckets.updated = Some(now);
+
+                let Buckets {
+                    requests, tokens, ..
+                } = &mut *buckets;
+                for bucket in requests.iter_mut().chain(tokens.iter_mut()) {
+                    bucket.refill(elapsed);
+                }
+
+                let wait = requests
+                    .iter()
+                    .map(|bucket| bucket.wait(1.0))
+                    .chain(tokens.iter().map(|bucket| bucket.wait(cost)))
+                    .max()
+                    .unwrap_or_default();
+                if wait.is_zero() {
+                    requests.iter_mut().for_each(|bucket| bucket.take(1.0));
+                    tokens.iter_mut().for_each(|bucket| bucket.take(cost));
+                    return;
+                }
+                wait
+            };
+
+            clock.sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::test_util::message_json;
+    use crate::{ClaudeRequest, Client, MockClock, Model, Result};
+    use serde_json::json;
+    use std::sync::Arc;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn request() -> ClaudeRequest {
+        ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .user("Hello")
+            .max_tokens(10)
+            .build()
+            .expect("Failed to build request")
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_paced_out() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(message_json(
+                json!([{"type": "text", "text": "Hello!"}]),
+                "end_turn",
+            )))
+            .expect(5)
+            .mount(&server)
+            .await;
+
+        // Two requests up front, then one every 100ms.
+        let clock = Arc::new(MockClock::new());
+        let client = Client::builder()
+            .api_key("test-key")
+            .clock(clock.clone())
+            .rate_limit(RateLimit {
+                requests: Some(2),
+                window: Duration::from_millis(200),
+                ..RateLimit::default()
+            })
+            .build()
+            .with_base_url(server.uri());
+
+        for _ in 0..5 {
+            client.call(&request()).await?;
+        }
+
+        assert_eq!(clock.sleeps(), vec![Duration::from_millis(100); 3]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_rate_limiter_spaces_requests_out() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    // The limit resets 200ms after each response was sent.
+                    .insert_header("date", "Thu, 01 Jan 1970 00:00:00 GMT")
+                    .insert_header("anthropic-ratelimit-requests-limit", "2")
+                    .insert_header("anthropic-ratelimit-requests-remaining", "1")
+                    .insert_header(
+                        "anthropic-ratelimit-requests-reset",
+                        "1970-01-01T00:00:00.2Z",
+                    )
+                    .set_body_json(message_json(
+                        json!([{"type": "text", "text": "Hello!"}]),
+                        "end_turn",
+                    )),
+            )
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let clock = Arc::new(MockClock::new());
+        let client = Client::with_api_key("test-key")
+            .with_base_url(server.uri())
+            .with_clock(clock.clone())
+            .with_rate_limiter(RateLimiter::new(0));
+
+        client.call(&request()).await?;
+        let request = request();
+        let calls = (0..2).map(|_| client.call(&request));
+        for response in futures::future::join_all(calls).await {
+            response?;
+        }
+
+        // One request was left, so the second of the pair waited for the reset.
+        assert_eq!(clock.sleeps(), vec![Duration::from_millis(200)]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tokens_are_charged() {
+        let limiter = Limiter::new(&RateLimit {
+            tokens: Some(1000),
+            window: Duration::from_millis(200),
+            ..RateLimit::default()
+        });
+        let clock = MockClock::new();
+
+        limiter.acquire(800, &clock).await;
+        limiter.acquire(10, &clock).await;
+        assert!(clock.sleeps().is_empty());
+
+        // 610 tokens are missing, refilled at 5 per millisecond.
+        limiter.acquire(800, &clock).await;
+        assert_eq!(clock.sleeps(), vec![Duration::from_millis(122)]);
+    }
+}
@@ -0,0 +1,292 @@
+//! Lenient parsing of slightly malformed JSON produced by the model.
+
+use crate::{
+    ClaudeRequest, ClaudeResponse, Client, ContentType, Error, Message, Result, Role, ToolResult,
+};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+/// Parses `text` as JSON, fixing up trailing commas, single-quoted strings and unquoted keys
+/// if strict parsing fails.
+pub fn repair_json(text: &str) -> Result<Value> {
+    match serde_json::from_str(text) {
+        Ok(value) => Ok(value),
+        Err(_) => Ok(serde_json::from_str(&rewrite(text))?),
+    }
+}
+
+/// Rewrites near-JSON into JSON, one character at a time.
+fn rewrite(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut out = String::with_capacity(text.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            quote @ ('"' | '\'') => {
+                out.push('"');
+                i += 1;
+                while i < chars.len() && chars[i] != quote {
+                    match chars[i] {
+                        '\\' if i + 1 < chars.len() => {
+                            if chars[i + 1] != '\'' {
+                                out.push('\\');
+                            }
+                            out.push(chars[i + 1]);
+                            i += 2;
+                            continue;
+                        }
+                        '"' => out.push_str("\\\""),
+                        c => out.push(c),
+                    }
+                    i += 1;
+                }
+                out.push('"');
+                i += 1;
+            }
+            ',' => {
+                let next = chars[i + 1..].iter().find(|c| !c.is_whitespace());
+                if !matches!(next, Some('}' | ']')) {
+                    out.push(',');
+                }
+                i += 1;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                let next = chars[i..].iter().find(|c| !c.is_whitespace());
+                if next == Some(&':') {
+                    out.push('"');
+                    out.push_str(&word);
+                    out.push('"');
+                } else {
+                    out.push_str(&word);
+                }
+            }
+            c => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    out
+}
+
+/// Replaces string values that hold (possibly malformed) JSON objects or arrays with the
+/// parsed value.
+///
+/// Models sometimes encode a nested object or array as a string instead of inline JSON.
+fn repair_value(value: Value) -> Value {
+    match value {
+        Value::String(text) => {
+            let trimmed = text.trim_start();
+            if trimmed.starts_with('{') || trimmed.starts_with('[') {
+                repair_json(&text).unwrap_or(Value::String(text))
+            } else {
+                Value::String(text)
+            }
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(repair_value).collect()),
+        Value::Object(fields) => Value::Object(
+            fields
+                .into_iter()
+                .map(|(key, value)| (key, repair_value(value)))
+                .collect(),
+        ),
+        value => value,
+    }
+}
+
+/// Returns the input of the first call to the tool `name`.
+fn tool_input(response: &ClaudeResponse, name: &str) -> Result<Value> {
+    response
+        .content
+        .iter()
+        .find_map(|content| match content {
+            ContentType::ToolUse(tool_use) if tool_use.name == name => Some(tool_use.input.clone()),
+            _ => None,
+        })
+        .ok_or_else(|| Error::MissingToolUse(name.to_string()))
+}
+
+impl Client {
+    /// Recovers a tool input that failed to parse as `T`.
+    ///
+    /// The input is repaired first. If it still does not parse, the parse error is sent back
+    /// as the tool result and the model is asked once more.
+    pub(crate) async fn repair_input<T: DeserializeOwned>(
+        &self,
+        mut request: ClaudeRequest,
+        response: ClaudeResponse,
+        name: &str,
+        error: serde_json::Error,
+    ) -> Result<T> {
+        let input = tool_input(&response, name)?;
+        if let Ok(value) = serde_json::from_value(repair_value(input)) {
+            return Ok(value);
+        }
+
+        let results = response
+            .content
+            .iter()
+            .filter_map(|content| match content {
+                ContentType::ToolUse(tool_use) => Some(ContentType::ToolResult(ToolResult::new(
+                    tool_use.id.clone(),
+                    format!(
+                        "The input could not be parsed: {}. Call the tool again with input \
+                         that matches its schema.",
+                        error
+                    ),
+                ))),
+                _ => None,
+            })
+            .collect();
+
+        request.messages.push(Message {
+            role: Role::Assistant,
+            content: response.content,
+        });
+        request.messages.push(Message {
+            role: Role::User,
+            content: results,
+        });
+
+        let response = self.call(&request).await?;
+        let input = tool_input(&response, name)?;
+
+        Ok(serde_json::from_value(repair_value(input))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::message_json;
+    use crate::{Model, ToolBuilder};
+    use schemars::JsonSchema;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use wiremock::matchers::{body_partial_json, body_string_contains, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[test]
+    fn test_repair_json() -> Result<()> {
+        assert_eq!(
+            repair_json("{'teams': ['Packers', 'Dolphins',], score: 31,}")?,
+            json!({"teams": ["Packers", "Dolphins"], "score": 31})
+        );
+        assert_eq!(
+            repair_json(r#"{"quote": "it's \"fine\"", "ok": true}"#)?,
+            json!({"quote": "it's \"fine\"", "ok": true})
+        );
+        assert!(repair_json("{'teams': [").is_err());
+
+        Ok(())
+    }
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema, PartialEq)]
+    struct SuperBowl {
+        year: u16,
+        teams: Vec<String>,
+    }
+
+    impl ToolBuilder for SuperBowl {
+        fn name() -> &'static str {
+            "extract_super_bowl_info"
+        }
+
+        fn description() -> Option<&'static str> {
+            Some("Extract Super Bowl information from text")
+        }
+    }
+
+    fn super_bowl_response(input: Value) -> ResponseTemplate {
+        ResponseTemplate::new(200).set_body_json(message_json(
+            json!([{
+                "type": "tool_use",
+                "id": "toolu_01",
+                "name": "extract_super_bowl_info",
+                "input": input
+            }]),
+            "tool_use",
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_extract_repairs_trailing_comma() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(super_bowl_response(json!({
+                "year": 1982,
+                "teams": "[\"Green Bay Packers\", \"Miami Dolphins\",]"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let super_bowl: SuperBowl = client
+            .extract(
+                Model::Haiku3,
+                "The Green Bay Packers beat the Miami Dolphins in the 1982 Super Bowl 31-10.",
+            )
+            .await?;
+
+        assert_eq!(
+            super_bowl,
+            SuperBowl {
+                year: 1982,
+                teams: vec![
+                    "Green Bay Packers".to_string(),
+                    "Miami Dolphins".to_string()
+                ],
+            }
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_extract_asks_again_when_repair_fails() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string_contains("The input could not be parsed"))
+            .and(body_partial_json(json!({
+                "messages": [
+                    {"role": "user"},
+                    {"role": "assistant", "content": [{"type": "tool_use", "id": "toolu_01"}]},
+                    {"role": "user", "content": [{"type": "tool_result", "tool_use_id": "toolu_01"}]}
+                ]
+            })))
+            .respond_with(super_bowl_response(
+                json!({"year": 1982, "teams": ["Green Bay Packers", "Miami Dolphins"]}),
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .respond_with(super_bowl_response(
+                json!({"year": "nineteen eighty-two", "teams": "Packers and Dolphins"}),
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let super_bowl: SuperBowl = client
+            .extract(
+                Model::Haiku3,
+                "The Green Bay Packers beat the Miami Dolphins in the 1982 Super Bowl 31-10.",
+            )
+            .await?;
+
+        assert_eq!(super_bowl.year, 1982);
+        assert_eq!(server.received_requests().await.map(|r| r.len()), Some(2));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+//! Retrying rate-limited and overloaded requests.
+
+use reqwest::header::{HeaderMap, DATE, RETRY_AFTER};
+use reqwest::StatusCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Status the API returns when it is temporarily overloaded.
+const OVERLOADED: u16 = 529;
+
+/// Rate limits reported in `anthropic-ratelimit-{limit}-remaining` and `-reset` headers.
+const RATE_LIMITS: [&str; 4] = ["requests", "tokens", "input-tokens", "output-tokens"];
+
+/// Controls how a [`Client`](crate::Client) retries failed requests.
+///
+/// Only rate limit (429) and overloaded (529) responses are retried; anything else,
+/// such as a 400, is returned immediately. A `retry-after` header on the response takes
+/// precedence over the computed backoff, up to `max_delay`.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each retry after it.
+    pub base_delay: Duration,
+    /// Upper bound on the delay between attempts.
+    pub max_delay: Duration,
+    /// Fraction of each delay, between 0.0 and 1.0, that is randomized away.
+    pub jitter: f64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Whether a response with `status` on attempt number `attempt` should be retried.
+    pub(crate) fn should_retry(&self, status: StatusCode, attempt: u32) -> bool {
+        is_retryable(status) && attempt < self.max_attempts
+    }
+
+    /// The delay before retrying after attempt number `attempt` failed.
+    pub(crate) fn delay(&self, attempt: u32, retry_after: Option<Duration>) -> Duration {
+        if let Some(retry_after) = retry_after {
+            return retry_after.min(self.max_delay);
+        }
+
+        let exponent = attempt.saturating_sub(1).min(31);
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0) * fastrand::f64();
+
+        delay.mul_f64(1.0 - jitter)
+    }
+}
+
+/// Whether `status` means the request may succeed later: rate limited (429) or overloaded (529).
+pub(crate) fn is_retryable(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status.as_u16() == OVERLOADED
+}
+
+/// Reads how long to wait before retrying from the response headers.
+///
+/// The `retry-after` header, given in seconds, is used when present. Otherwise the wait
+/// lasts until the latest reset of an exhausted rate limit.
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let seconds = header(headers, RETRY_AFTER.as_str()).and_then(|value| value.parse().ok());
+    match seconds {
+        Some(seconds) => Duration::try_from_secs_f64(seconds).ok(),
+        None => rate_limit_reset(headers, SystemTime::now()),
+    }
+}
+
+/// Returns the time until the latest reset of an exhausted rate limit.
+///
+/// Reset headers are absolute times on the server's clock, so the wait is measured from the
+/// response's `date` header rather than the local clock, which may be skewed. `now` is
+/// used when the response has no `date`.
+fn rate_limit_reset(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let reset = RATE_LIMITS
+        .iter()
+        .filter(|limit| {
+            header(headers, &format!("anthropic-ratelimit-{}-remaining", limit)) == Some("0")
+        })
+        .filter_map(|limit| header(headers, &format!("anthropic-ratelimit-{}-reset", limit)))
+        .filter_map(parse_rfc3339)
+        .max()?;
+
+    Some(
+        reset
+            .duration_since(server_now(headers, now))
+            .unwrap_or_default(),
+    )
+}
+
+/// Returns the time the response was sent on the server's clock, from its `date` header.
+///
+/// Falls back to `now` when the response has no `date`.
+pub(crate) fn server_now(headers: &HeaderMap, now: SystemTime) -> SystemTime {
+    header(headers, DATE.as_str())
+        .and_then(|date| httpdate::parse_http_date(date).ok())
+        .unwrap_or(now)
+}
+
+pub(crate) fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    Some(headers.get(name)?.to_str().ok()?.trim())
+}
+
+/// Parses an RFC 3339 timestamp such as `2024-09-24T18:37:24.100435Z`.
+///
+/// Only years from 1970 to 9999 are accepted, so malformed headers cannot overflow.
+pub(crate) fn parse_rfc3339(text: &str) -> Option<SystemTime> {
+    let (date, time) = text.split_once(['T', 't', ' '])?;
+
+    let mut date = date.splitn(3, '-');
+    let year: i64 = date.next()?.parse().ok()?;
+    let month: i64 = date.next()?.parse().ok()?;
+    let day: i64 = date.next()?.parse().ok()?;
+
+    let (clock, offset) = match time.strip_suffix(['Z', 'z']) {
+        Some(clock) => (clock, 0),
+        None => {
+            let (clock, offset) = time.split_at(time.rfind(['+', '-'])?);
+            let (hours, minutes) = offset[1..].split_once(':')?;
+            let (hours, minutes) = (hours.parse::<i64>().ok()?, minutes.parse::<i64>().ok()?);
+            if !(0..=23).contains(&hours) || !(0..=59).contains(&minutes) {
+                return None;
+            }
+            let seconds = hours * 3600 + minutes * 60;
+            (
+                clock,
+                if offset.starts_with('-') {
+                    -seconds
+                } else {
+                    seconds
+                },
+            )
+        }
+    };
+
+    let (clock, fraction) = clock.split_once('.').unwrap_or((clock, ""));
+    let mut clock = clock.splitn(3, ':');
+    let hour: i64 = clock.next()?.parse().ok()?;
+    let minute: i64 = clock.next()?.parse().ok()?;
+    let second: i64 = clock.next()?.parse().ok()?;
+    let nanos: u32 = if fraction.is_empty() {
+        0
+    } else {
+        format!("{:0<9}", &fraction[..fraction.len().min(9)])
+            .parse()
+            .ok()?
+    };
+
+    if !(1970..=9999).contains(&year)
+        || !(1..=12).contains(&month)
+        || !(1..=days_in_month(year, month)).contains(&day)
+        || !(0..=23).contains(&hour)
+        || !(0..=59).contains(&minute)
+        || !(0..=59).contains(&second)
+    {
+        return None;
+    }
+
+    let seconds =
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+
+    Some(UNIX_EPOCH + Duration::new(u64::try_from(seconds).ok()?, nanos))
+}
+
+/// Returns the number of days in `month` (1 to 12) of `year`.
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the number of days between 1970-01-01 and the given date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // See http://howardhinnant.github.io/date_algorithms.html#days_from_civil.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_delay_backs_off_exponentially_up_to_max() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: 0.0,
+        };
+
+        assert_eq!(policy.delay(1, None), Duration::from_millis(100));
+        assert_eq!(policy.delay(2, None), Duration::from_millis(200));
+        assert_eq!(policy.delay(3, None), Duration::from_millis(400));
+        assert_eq!(policy.delay(4, None), Duration::from_millis(500));
+        assert_eq!(
+            policy.delay(1, Some(Duration::from_millis(300))),
+            Duration::from_millis(300)
+        );
+    }
+
+    #[test]
+    fn test_oversized_retry_after_is_capped() {
+        let policy = RetryPolicy {
+            max_delay: Duration::from_secs(30),
+            ..RetryPolicy::default()
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("1e9"));
+
+        assert_eq!(
+            policy.delay(1, retry_after(&headers)),
+            Duration::from_secs(30)
+        );
+    }
+
+    #[test]
+    fn test_retry_after_header() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("3"));
+
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(3)));
+    }
+
+    #[test]
+    fn test_parse_rfc3339() {
+        assert_eq!(
+            parse_rfc3339("1970-01-02T00:00:01Z"),
+            Some(UNIX_EPOCH + Duration::from_secs(86401))
+        );
+        assert_eq!(
+            parse_rfc3339("2024-09-24T18:37:24.5+02:00"),
+            Some(UNIX_EPOCH + Duration::new(1727195844, 500_000_000))
+        );
+        assert_eq!(parse_rfc3339("not a date"), None);
+    }
+
+    #[test]
+    fn test_parse_rfc3339_rejects_invalid_dates() {
+        assert_eq!(parse_rfc3339("2024-02-31T00:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2023-02-29T00:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2024-04-31T00:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2024-09-24T18:37:60Z"), None);
+        assert_eq!(parse_rfc3339("99999999999999-01-01T00:00:00Z"), None);
+        assert_eq!(parse_rfc3339("2024-09-24T18:37:24+99999999999999:00"), None);
+        assert_eq!(parse_rfc3339("2024-09-24T-99999999999999:00:00Z"), None);
+        assert!(parse_rfc3339("2024-02-29T00:00:00Z").is_some());
+        assert!(parse_rfc3339("2000-02-29T23:59:59Z").is_some());
+    }
+
+    #[test]
+    fn test_rate_limit_reset_uses_server_date() {
+        let local_now = UNIX_EPOCH + Duration::from_secs(1_727_190_000);
+        // The server's clock is an hour ahead of the local one.
+        let server_now = local_now + Duration::from_secs(3600);
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            DATE,
+            HeaderValue::from_str(&httpdate::fmt_http_date(server_now)).unwrap(),
+        );
+        headers.insert(
+            "anthropic-ratelimit-requests-remaining",
+            HeaderValue::from_static("0"),
+        );
+        headers.insert(
+            "anthropic-ratelimit-requests-reset",
+            HeaderValue::from_static("2024-09-24T16:00:10Z"),
+        );
+        headers.insert(
+            "anthropic-ratelimit-tokens-remaining",
+            HeaderValue::from_static("5000"),
+        );
+        headers.insert(
+            "anthropic-ratelimit-tokens-reset",
+            HeaderValue::from_static("2024-09-24T17:00:00Z"),
+        );
+
+        assert_eq!(
+            rate_limit_reset(&headers, local_now),
+            Some(Duration::from_secs(10))
+        );
+
+        headers.remove(DATE);
+        assert_eq!(
+            rate_limit_reset(&headers, local_now),
+            Some(Duration::from_secs(3610))
+        );
+    }
+}
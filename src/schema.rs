@@ -0,0 +1,233 @@
+//! Checking JSON values against the JSON Schemas generated for tool inputs.
+
+use serde_json::{Map, Value};
+
+/// Checks `value` against `schema`, resolving `$ref`s in `definitions`.
+///
+/// Supports the keywords schemars generates: `type`, `enum`, `const`, numeric and length
+/// bounds, `properties`, `required`, `additionalProperties`, `items`, `$ref`, `allOf`,
+/// `anyOf` and `oneOf`. Other keywords are ignored. The error names the path of the first
+/// offending value, e.g. `/teams/0/name`.
+pub(crate) fn validate(
+    value: &Value,
+    schema: &Value,
+    definitions: &Map<String, Value>,
+) -> Result<(), String> {
+    validate_at(value, schema, definitions, "")
+}
+
+fn validate_at(
+    value: &Value,
+    schema: &Value,
+    definitions: &Map<String, Value>,
+    path: &str,
+) -> Result<(), String> {
+    let schema = match schema {
+        Value::Bool(true) => return Ok(()),
+        Value::Bool(false) => return Err(format!("{}: no value is allowed", at(path))),
+        Value::Object(schema) => schema,
+        _ => return Ok(()),
+    };
+
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        let name = reference
+            .strip_prefix("#/$defs/")
+            .or_else(|| reference.strip_prefix("#/definitions/"));
+        let definition = name
+            .and_then(|name| definitions.get(name))
+            .ok_or_else(|| format!("{}: unknown reference `{}`", at(path), reference))?;
+        validate_at(value, definition, definitions, path)?;
+    }
+
+    for schema in subschemas(schema, "allOf") {
+        validate_at(value, schema, definitions, path)?;
+    }
+    let any_of = subschemas(schema, "anyOf");
+    if !any_of.is_empty()
+        && !any_of
+            .iter()
+            .any(|schema| validate_at(value, schema, definitions, path).is_ok())
+    {
+        return Err(format!(
+            "{}: {} matches none of the allowed schemas",
+            at(path),
+            value
+        ));
+    }
+    let one_of = subschemas(schema, "oneOf");
+    if !one_of.is_empty()
+        && one_of
+            .iter()
+            .filter(|schema| validate_at(value, schema, definitions, path).is_ok())
+            .count()
+            != 1
+    {
+        return Err(format!(
+            "{}: {} must match exactly one schema",
+            at(path),
+            value
+        ));
+    }
+
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            return Err(format!(
+                "{}: {} is not one of {:?}",
+                at(path),
+                value,
+                allowed
+            ));
+        }
+    }
+    if let Some(constant) = schema.get("const") {
+        if value != constant {
+            return Err(format!("{}: {} is not {}", at(path), value, constant));
+        }
+    }
+    if let Some(types) = schema.get("type") {
+        let types: Vec<&str> = match types {
+            Value::String(name) => vec![name],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|name| has_type(value, name)) {
+            return Err(format!(
+                "{}: expected {}, got {}",
+                at(path),
+                types.join(" or "),
+                value
+            ));
+        }
+    }
+
+    match value {
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_f64);
+            if let Some(minimum) = bound("minimum").filter(|minimum| number < *minimum) {
+                return Err(format!("{}: {} is less than {}", at(path), number, minimum));
+            }
+            if let Some(maximum) = bound("maximum").filter(|maximum| number > *maximum) {
+                return Err(format!(
+                    "{}: {} is greater than {}",
+                    at(path),
+                    number,
+                    maximum
+                ));
+            }
+            if let Some(minimum) = bound("exclusiveMinimum").filter(|minimum| number <= *minimum) {
+                return Err(format!("{}: {} is not above {}", at(path), number, minimum));
+            }
+            if let Some(maximum) = bound("exclusiveMaximum").filter(|maximum| number >= *maximum) {
+                return Err(format!("{}: {} is not below {}", at(path), number, maximum));
+            }
+        }
+        Value::String(text) => check_len(
+            path,
+            "characters",
+            text.chars().count(),
+            schema,
+            "minLength",
+            "maxLength",
+        )?,
+        Value::Array(items) => {
+            check_len(path, "items", items.len(), schema, "minItems", "maxItems")?;
+            match schema.get("items") {
+                Some(Value::Array(schemas)) => {
+                    for (i, (item, schema)) in items.iter().zip(schemas).enumerate() {
+                        validate_at(item, schema, definitions, &format!("{}/{}", path, i))?;
+                    }
+                }
+                Some(schema) => {
+                    for (i, item) in items.iter().enumerate() {
+                        validate_at(item, schema, definitions, &format!("{}/{}", path, i))?;
+                    }
+                }
+                None => {}
+            }
+        }
+        Value::Object(fields) => {
+            let empty = Map::new();
+            let properties = schema
+                .get("properties")
+                .and_then(Value::as_object)
+                .unwrap_or(&empty);
+            for required in schema
+                .get("required")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                if !fields.contains_key(required) {
+                    return Err(format!("{}: missing field `{}`", at(path), required));
+                }
+            }
+            for (name, field) in fields {
+                let path = format!("{}/{}", path, name);
+                match (properties.get(name), schema.get("additionalProperties")) {
+                    (Some(schema), _) | (None, Some(schema @ Value::Object(_))) => {
+                        validate_at(field, schema, definitions, &path)?
+                    }
+                    (None, Some(Value::Bool(false))) => {
+                        return Err(format!("{}: unknown property", path));
+                    }
+                    (None, _) => {}
+                }
+            }
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn subschemas<'a>(schema: &'a Map<String, Value>, keyword: &str) -> &'a [Value] {
+    schema
+        .get(keyword)
+        .and_then(Value::as_array)
+        .map_or(&[], Vec::as_slice)
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "number" => value.is_number(),
+        "integer" => {
+            value.is_i64() || value.is_u64() || value.as_f64().is_some_and(|n| n.fract() == 0.0)
+        }
+        "string" => value.is_string(),
+        _ => true,
+    }
+}
+
+fn check_len(
+    path: &str,
+    unit: &str,
+    len: usize,
+    schema: &Map<String, Value>,
+    min: &str,
+    max: &str,
+) -> Result<(), String> {
+    let bound = |keyword: &str| schema.get(keyword).and_then(Value::as_u64);
+    if let Some(min) = bound(min).filter(|min| (len as u64) < *min) {
+        return Err(format!("{}: fewer than {} {}", at(path), min, unit));
+    }
+    if let Some(max) = bound(max).filter(|max| (len as u64) > *max) {
+        return Err(format!("{}: more than {} {}", at(path), max, unit));
+    }
+
+    Ok(())
+}
+
+/// Names `path` in an error, with `/` for the input itself.
+fn at(path: &str) -> &str {
+    if path.is_empty() {
+        "/"
+    } else {
+        path
+    }
+}
@@ -0,0 +1,864 @@
+//! Streaming responses over server-sent events.
+
+use crate::error::ApiErrorBody;
+use crate::{ClaudeResponse, ContentType, Error, Result, StopReason};
+use futures::{Stream, StreamExt};
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::VecDeque;
+use std::io::Write;
+
+/// Represents an event in a streamed response.
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// The message has started. Content is empty and usage covers the input tokens.
+    MessageStart { message: ClaudeResponse },
+    /// A content block has started at `index`.
+    ContentBlockStart {
+        index: usize,
+        content_block: ContentType,
+    },
+    /// Text was appended to the text block at `index`.
+    TextDelta { index: usize, text: String },
+    /// A fragment of the tool input JSON was appended to the tool-use block at `index`.
+    InputJsonDelta { index: usize, partial_json: String },
+    /// Reasoning was appended to the thinking block at `index`.
+    ThinkingDelta { index: usize, thinking: String },
+    /// The signature of the thinking block at `index`, sent before the block stops.
+    SignatureDelta { index: usize, signature: String },
+    /// The content block at `index` is complete.
+    ContentBlockStop { index: usize },
+    /// Top-level changes to the message, including the final usage.
+    MessageDelta {
+        stop_reason: Option<StopReason>,
+        stop_sequence: Option<String>,
+        usage: DeltaUsage,
+    },
+    /// The message is complete.
+    MessageStop,
+    /// Keep-alive sent by the API.
+    Ping,
+}
+
+impl StreamEvent {
+    /// Returns the reason the message stopped, which is only sent in a `message_delta` event.
+    ///
+    /// A streaming tool loop should check for [`StopReason::ToolUse`] here to decide whether
+    /// to run the requested tools and continue.
+    pub fn stop_reason(&self) -> Option<&StopReason> {
+        match self {
+            StreamEvent::MessageDelta { stop_reason, .. } => stop_reason.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+impl Serialize for StreamEvent {
+    /// Serializes the event in the shape the API sends it, e.g.
+    /// `{"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", ...}}`.
+    fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let event = match self {
+            StreamEvent::MessageStart { message } => {
+                json!({"type": "message_start", "message": message})
+            }
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => json!({
+                "type": "content_block_start",
+                "index": index,
+                "content_block": content_block
+            }),
+            StreamEvent::TextDelta { index, text } => json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": {"type": "text_delta", "text": text}
+            }),
+            StreamEvent::InputJsonDelta {
+                index,
+                partial_json,
+            } => json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": {"type": "input_json_delta", "partial_json": partial_json}
+            }),
+            StreamEvent::ThinkingDelta { index, thinking } => json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": {"type": "thinking_delta", "thinking": thinking}
+            }),
+            StreamEvent::SignatureDelta { index, signature } => json!({
+                "type": "content_block_delta",
+                "index": index,
+                "delta": {"type": "signature_delta", "signature": signature}
+            }),
+            StreamEvent::ContentBlockStop { index } => {
+                json!({"type": "content_block_stop", "index": index})
+            }
+            StreamEvent::MessageDelta {
+                stop_reason,
+                stop_sequence,
+                usage,
+            } => json!({
+                "type": "message_delta",
+                "delta": {"stop_reason": stop_reason, "stop_sequence": stop_sequence},
+                "usage": usage
+            }),
+            StreamEvent::MessageStop => json!({"type": "message_stop"}),
+            StreamEvent::Ping => json!({"type": "ping"}),
+        };
+
+        event.serialize(serializer)
+    }
+}
+
+/// Writes each event to `writer` as a line of JSON, stopping after `message_stop`.
+///
+/// The writer is flushed after every line so readers see events as they arrive.
+pub(crate) async fn write_ndjson<S>(events: S, mut writer: impl Write) -> Result<()>
+where
+    S: Stream<Item = Result<StreamEvent>>,
+{
+    let mut events = std::pin::pin!(events);
+    while let Some(event) = events.next().await {
+        let event = event?;
+        serde_json::to_writer(&mut writer, &event)?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+        if matches!(event, StreamEvent::MessageStop) {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+/// Adapters that regroup the text of a streamed response.
+///
+/// Deltas are buffered until a complete line or sentence is available, which suits
+/// text-to-speech or line-buffered output better than token-sized pieces. Whatever remains
+/// is flushed at `message_stop`. Events other than text deltas are dropped; errors are passed
+/// through.
+pub trait TextStreamExt: Stream<Item = Result<StreamEvent>> + Sized {
+    /// Yields the streamed text one line at a time, without the trailing newline.
+    fn by_line(self) -> impl Stream<Item = Result<String>> {
+        regroup(self, Grouping::Line)
+    }
+
+    /// Yields the streamed text one sentence at a time, including its closing punctuation.
+    ///
+    /// A sentence ends at `.`, `!` or `?` followed by whitespace, or at a newline. Surrounding
+    /// whitespace is trimmed.
+    fn by_sentence(self) -> impl Stream<Item = Result<String>> {
+        regroup(self, Grouping::Sentence)
+    }
+}
+
+impl<S: Stream<Item = Result<StreamEvent>>> TextStreamExt for S {}
+
+#[derive(Debug, Clone, Copy)]
+enum Grouping {
+    Line,
+    Sentence,
+}
+
+impl Grouping {
+    /// Removes the next complete piece from the front of `buffer`, if there is one.
+    fn take(self, buffer: &mut String) -> Option<String> {
+        let (end, rest) = match self {
+            Grouping::Line => buffer.find('\n').map(|end| (end, end + 1))?,
+            Grouping::Sentence => {
+                let mut chars = buffer.char_indices().peekable();
+                loop {
+                    match (chars.next()?, chars.peek()) {
+                        ((i, '\n'), _) => break (i, i + 1),
+                        ((i, '.' | '!' | '?'), Some((_, next))) if next.is_whitespace() => {
+                            break (i + 1, i + 1)
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        };
+
+        let piece = buffer[..end].to_string();
+        buffer.drain(..rest);
+        Some(piece)
+    }
+
+    /// Tidies a piece, returning `None` if it should be skipped.
+    fn finish(self, piece: String) -> Option<String> {
+        match self {
+            Grouping::Line => Some(piece),
+            Grouping::Sentence => {
+                let piece = piece.trim();
+                (!piece.is_empty()).then(|| piece.to_string())
+            }
+        }
+    }
+}
+
+/// Buffers text deltas and yields them regrouped into lines or sentences.
+fn regroup<S>(events: S, grouping: Grouping) -> impl Stream<Item = Result<String>>
+where
+    S: Stream<Item = Result<StreamEvent>>,
+{
+    struct State<S> {
+        events: std::pin::Pin<Box<S>>,
+        buffer: String,
+        done: bool,
+    }
+
+    let state = State {
+        events: Box::pin(events),
+        buffer: String::new(),
+        done: false,
+    };
+
+    futures::stream::unfold(state, move |mut state| async move {
+        loop {
+            if let Some(piece) = grouping.take(&mut state.buffer) {
+                match grouping.finish(piece) {
+                    Some(piece) => return Some((Ok(piece), state)),
+                    None => continue,
+                }
+            }
+
+            if state.done {
+                if state.buffer.is_empty() {
+                    return None;
+                }
+                let piece = std::mem::take(&mut state.buffer);
+                return grouping.finish(piece).map(|piece| (Ok(piece), state));
+            }
+
+            match state.events.next().await {
+                Some(Ok(StreamEvent::TextDelta { text, .. })) => state.buffer.push_str(&text),
+                Some(Ok(StreamEvent::MessageStop)) | None => state.done = true,
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Some((Err(e), state)),
+            }
+        }
+    })
+}
+
+/// Assembles the events of a streamed response into the complete [`ClaudeResponse`].
+///
+/// Text and thinking deltas are appended to their blocks and tool input JSON is parsed once
+/// its block stops. If the stream closes between blocks before `message_stop`, the blocks
+/// received so far are returned with [`incomplete`](ClaudeResponse::incomplete) set. Fails
+/// if the stream ends with a block still open, or before `message_start`.
+pub(crate) async fn collect<S>(events: S) -> Result<ClaudeResponse>
+where
+    S: Stream<Item = Result<StreamEvent>>,
+{
+    let mut events = std::pin::pin!(events);
+    let mut message: Option<ClaudeResponse> = None;
+    let mut partial_json: Vec<String> = Vec::new();
+    let mut open_blocks: Vec<usize> = Vec::new();
+    let mut stopped = false;
+
+    while let Some(event) = events.next().await {
+        let event = event?;
+        if let StreamEvent::MessageStart { message: start } = event {
+            message = Some(start);
+            continue;
+        }
+        let Some(message) = message.as_mut() else {
+            return Err(Error::Stream(
+                "stream did not start with message_start".to_string(),
+            ));
+        };
+
+        match event {
+            StreamEvent::ContentBlockStart {
+                index,
+                content_block,
+            } => {
+                if index >= message.content.len() {
+                    message
+                        .content
+                        .resize(index + 1, ContentType::Unknown(serde_json::Value::Null));
+                    partial_json.resize(index + 1, String::new());
+                }
+                message.content[index] = content_block;
+                open_blocks.push(index);
+            }
+            StreamEvent::TextDelta { index, text } => {
+                if let Some(ContentType::Text { text: block, .. }) = message.content.get_mut(index)
+                {
+                    block.push_str(&text);
+                }
+            }
+            StreamEvent::InputJsonDelta {
+                index,
+                partial_json: fragment,
+            } => {
+                if let Some(json) = partial_json.get_mut(index) {
+                    json.push_str(&fragment);
+                }
+            }
+            StreamEvent::ThinkingDelta { index, thinking } => {
+                if let Some(ContentType::Thinking {
+                    thinking: block, ..
+                }) = message.content.get_mut(index)
+                {
+                    block.push_str(&thinking);
+                }
+            }
+            StreamEvent::SignatureDelta { index, signature } => {
+                if let Some(ContentType::Thinking {
+                    signature: block, ..
+                }) = message.content.get_mut(index)
+                {
+                    block.push_str(&signature);
+                }
+            }
+            StreamEvent::ContentBlockStop { index } => {
+                open_blocks.retain(|open| *open != index);
+                let json = partial_json.get(index).filter(|json| !json.is_empty());
+                let input = match message.content.get_mut(index) {
+                    Some(ContentType::ToolUse(tool_use)) => Some(&mut tool_use.input),
+                    Some(ContentType::ServerToolUse { input, .. }) => Some(input),
+                    _ => None,
+                };
+                if let (Some(input), Some(json)) = (input, json) {
+                    *input = serde_json::from_str(json)?;
+                }
+            }
+            StreamEvent::MessageDelta {
+                stop_reason,
+                stop_sequence,
+                usage,
+            } => {
+                message.stop_reason = stop_reason;
+                message.stop_sequence = stop_sequence;
+                message.usage.output_tokens = usage.output_tokens;
+                if usage.thinking_tokens.is_some() {
+                    message.usage.thinking_tokens = usage.thinking_tokens;
+                }
+            }
+            StreamEvent::MessageStop => {
+                stopped = true;
+                break;
+            }
+            StreamEvent::MessageStart { .. } | StreamEvent::Ping => {}
+        }
+    }
+
+    let Some(mut message) = message else {
+        return Err(Error::Stream(
+            "stream ended before message_start".to_string(),
+        ));
+    };
+    if !stopped {
+        // Blocks that were cut off may hold half a tool input, so only whole blocks are kept.
+        if !open_blocks.is_empty() {
+            return Err(Error::Stream(
+                "stream ended in the middle of a content block".to_string(),
+            ));
+        }
+        message.incomplete = true;
+    } else if message.stop_reason.is_none() {
+        return Err(Error::Stream(
+            "stream ended before the message was complete".to_string(),
+        ));
+    }
+
+    Ok(message)
+}
+
+/// Represents the cumulative usage reported in a `message_delta` event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeltaUsage {
+    pub output_tokens: u32,
+    /// Output tokens spent on extended thinking so far, when reported separately.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thinking_tokens: Option<u32>,
+}
+
+/// Represents the raw event payload as sent on the wire.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RawEvent {
+    MessageStart {
+        message: ClaudeResponse,
+    },
+    ContentBlockStart {
+        index: usize,
+        content_block: ContentType,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: RawDelta,
+    },
+    ContentBlockStop {
+        index: usize,
+    },
+    MessageDelta {
+        delta: RawMessageDelta,
+        usage: DeltaUsage,
+    },
+    MessageStop,
+    Ping,
+    Error(ApiErrorBody),
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RawDelta {
+    TextDelta {
+        text: String,
+    },
+    InputJsonDelta {
+        partial_json: String,
+    },
+    ThinkingDelta {
+        thinking: String,
+    },
+    SignatureDelta {
+        signature: String,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawMessageDelta {
+    stop_reason: Option<StopReason>,
+    stop_sequence: Option<String>,
+}
+
+/// Parses the `data` of a single server-sent event.
+///
+/// Returns `None` for events and deltas this crate does not know about, which the API
+/// documents may be added at any time.
+fn parse_event(data: &str) -> Result<Option<StreamEvent>> {
+    let raw: RawEvent = serde_json::from_str(data)?;
+
+    let event = match raw {
+        RawEvent::MessageStart { message } => StreamEvent::MessageStart { message },
+        RawEvent::ContentBlockStart {
+            index,
+            content_block,
+        } => StreamEvent::ContentBlockStart {
+            index,
+            content_block,
+        },
+        RawEvent::ContentBlockDelta { index, delta } => match delta {
+            RawDelta::TextDelta { text } => StreamEvent::TextDelta { index, text },
+            RawDelta::InputJsonDelta { partial_json } => StreamEvent::InputJsonDelta {
+                index,
+                partial_json,
+            },
+            RawDelta::ThinkingDelta { thinking } => StreamEvent::ThinkingDelta { index, thinking },
+            RawDelta::SignatureDelta { signature } => {
+                StreamEvent::SignatureDelta { index, signature }
+            }
+            RawDelta::Unknown => return Ok(None),
+        },
+        RawEvent::ContentBlockStop { index } => StreamEvent::ContentBlockStop { index },
+        RawEvent::MessageDelta { delta, usage } => StreamEvent::MessageDelta {
+            stop_reason: delta.stop_reason,
+            stop_sequence: delta.stop_sequence,
+            usage,
+        },
+        RawEvent::MessageStop => StreamEvent::MessageStop,
+        RawEvent::Ping => StreamEvent::Ping,
+        RawEvent::Error(ApiErrorBody { error }) => {
+            return Err(Error::Api {
+                status: StatusCode::OK,
+                error_type: error.error_type,
+                message: error.message,
+            })
+        }
+        RawEvent::Unknown => return Ok(None),
+    };
+
+    Ok(Some(event))
+}
+
+/// Splits a byte stream into server-sent event `data` payloads.
+#[derive(Debug, Default)]
+struct SseDecoder {
+    buffer: Vec<u8>,
+}
+
+impl SseDecoder {
+    fn push(&mut self, chunk: &[u8]) {
+        self.buffer.extend(chunk.iter().filter(|&&b| b != b'\r'));
+    }
+
+    /// Returns the data of the next complete event, if one has been buffered.
+    fn next_data(&mut self) -> Result<Option<String>> {
+        while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let frame: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            let frame = String::from_utf8(frame)
+                .map_err(|_| Error::Stream("event is not valid UTF-8".to_string()))?;
+
+            let data: Vec<&str> = frame
+                .lines()
+                .filter_map(|line| line.strip_prefix("data:"))
+                .map(|data| data.strip_prefix(' ').unwrap_or(data))
+                .collect();
+
+            if !data.is_empty() {
+                return Ok(Some(data.join("\n")));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Treats whatever remains in the buffer as a final event.
+    fn finish(&mut self) -> Result<Option<String>> {
+        if self.buffer.iter().all(u8::is_ascii_whitespace) {
+            return Ok(None);
+        }
+        self.buffer.extend_from_slice(b"\n\n");
+        self.next_data()
+    }
+}
+
+/// Decodes a stream of response body chunks into [`StreamEvent`]s.
+///
+/// Fails with [`Error::ResponseTooLarge`] once more than `limit` bytes have been received.
+pub(crate) fn decode<S, B, E>(
+    bytes: S,
+    limit: Option<usize>,
+) -> impl Stream<Item = Result<StreamEvent>>
+where
+    S: Stream<Item = std::result::Result<B, E>>,
+    B: AsRef<[u8]>,
+    E: Into<Error>,
+{
+    struct State<S> {
+        bytes: std::pin::Pin<Box<S>>,
+        decoder: SseDecoder,
+        pending: VecDeque<String>,
+        received: usize,
+        done: bool,
+    }
+
+    let state = State {
+        bytes: Box::pin(bytes),
+        decoder: SseDecoder::default(),
+        pending: VecDeque::new(),
+        received: 0,
+        done: false,
+    };
+
+    futures::stream::unfold(state, move |mut state| async move {
+        loop {
+            if let Some(data) = state.pending.pop_front() {
+                match parse_event(&data) {
+                    Ok(Some(event)) => return Some((Ok(event), state)),
+                    Ok(None) => continue,
+                    Err(e) => return Some((Err(e), state)),
+                }
+            }
+
+            let next = match state.decoder.next_data() {
+                Ok(next) => next,
+                Err(e) => return Some((Err(e), state)),
+            };
+            if let Some(data) = next {
+                state.pending.push_back(data);
+                continue;
+            }
+
+            if state.done {
+                return None;
+            }
+
+            match state.bytes.next().await {
+                Some(Ok(chunk)) => {
+                    let chunk = chunk.as_ref();
+                    state.received += chunk.len();
+                    if let Some(limit) = limit.filter(|limit| state.received > *limit) {
+                        state.done = true;
+                        state.pending.clear();
+                        return Some((Err(Error::ResponseTooLarge { limit }), state));
+                    }
+                    state.decoder.push(chunk);
+                }
+                Some(Err(e)) => {
+                    state.done = true;
+                    return Some((Err(e.into()), state));
+                }
+                None => {
+                    state.done = true;
+                    match state.decoder.finish() {
+                        Ok(Some(data)) => state.pending.push_back(data),
+                        Ok(None) => {}
+                        Err(e) => return Some((Err(e), state)),
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::message_json;
+    use futures::TryStreamExt;
+
+    const FIXTURE: &str = include_str!("../tests/fixtures/stream_tool_use.sse");
+
+    #[tokio::test]
+    async fn test_decode_canned_stream() -> Result<()> {
+        // Split on odd boundaries to make sure events spanning chunks are reassembled.
+        let chunks: Vec<Result<Vec<u8>>> = FIXTURE
+            .as_bytes()
+            .chunks(7)
+            .map(|chunk| Ok(chunk.to_vec()))
+            .collect();
+
+        let events: Vec<StreamEvent> = decode(futures::stream::iter(chunks), None)
+            .try_collect()
+            .await?;
+
+        assert!(matches!(events[0], StreamEvent::MessageStart { .. }));
+        assert!(matches!(events.last(), Some(StreamEvent::MessageStop)));
+
+        let text: String = events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::TextDelta { index: 0, text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(text, "Okay, let's check the weather.");
+
+        let json: String = events
+            .iter()
+            .filter_map(|event| match event {
+                StreamEvent::InputJsonDelta {
+                    index: 1,
+                    partial_json,
+                } => Some(partial_json.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(json, r#"{"location": "San Francisco, CA"}"#);
+
+        assert!(events.iter().any(|event| matches!(
+            event,
+            StreamEvent::ContentBlockStart {
+                index: 1,
+                content_block: ContentType::ToolUse(_)
+            }
+        )));
+        assert!(events.iter().any(|event| matches!(
+            event,
+            StreamEvent::MessageDelta {
+                stop_reason: Some(StopReason::ToolUse),
+                usage: DeltaUsage {
+                    output_tokens: 89,
+                    thinking_tokens: None
+                },
+                ..
+            }
+        )));
+
+        Ok(())
+    }
+
+    fn text_deltas(words: &[&str]) -> Vec<Result<StreamEvent>> {
+        words
+            .iter()
+            .map(|word| {
+                Ok(StreamEvent::TextDelta {
+                    index: 0,
+                    text: word.to_string(),
+                })
+            })
+            .chain([Ok(StreamEvent::MessageStop)])
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_text_by_line() -> Result<()> {
+        let events = text_deltas(&[
+            "Roses",
+            " are",
+            " red,\nViolets",
+            " are",
+            " blue",
+            "\n\nThe",
+            " end",
+        ]);
+
+        let lines: Vec<String> = futures::stream::iter(events)
+            .by_line()
+            .try_collect()
+            .await?;
+
+        assert_eq!(
+            lines,
+            vec!["Roses are red,", "Violets are blue", "", "The end"]
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_text_by_sentence() -> Result<()> {
+        let events = text_deltas(&["Pi", " is", " 3.14.", " Is", " it", " rational?", " No"]);
+
+        let sentences: Vec<String> = futures::stream::iter(events)
+            .by_sentence()
+            .try_collect()
+            .await?;
+
+        assert_eq!(sentences, vec!["Pi is 3.14.", "Is it rational?", "No"]);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_collect_matches_non_streamed_response() -> Result<()> {
+        let chunks: Vec<Result<&[u8]>> = vec![Ok(FIXTURE.as_bytes())];
+
+        let response = collect(decode(futures::stream::iter(chunks), None)).await?;
+
+        let mut expected = message_json(
+            serde_json::json!([
+                {"type": "text", "text": "Okay, let's check the weather."},
+                {
+                    "type": "tool_use",
+                    "id": "toolu_01T1x1fJ34qAmk2tNTrN7Up6",
+                    "name": "get_weather",
+                    "input": {"location": "San Francisco, CA"}
+                }
+            ]),
+            "tool_use",
+        );
+        expected["id"] = serde_json::json!("msg_014p7gG3wDgGV9EUtLvnow3U");
+        expected["usage"] = serde_json::json!({"input_tokens": 472, "output_tokens": 89});
+        let expected: ClaudeResponse = serde_json::from_value(expected)?;
+        assert_eq!(
+            serde_json::to_value(&response)?,
+            serde_json::to_value(&expected)?
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_collect_streamed_thinking() -> Result<()> {
+        let fixture = include_str!("../tests/fixtures/stream_thinking.sse");
+        let chunks: Vec<Result<&[u8]>> = vec![Ok(fixture.as_bytes())];
+
+        let response = collect(decode(futures::stream::iter(chunks), None)).await?;
+
+        assert_eq!(
+            response.content,
+            vec![
+                ContentType::Thinking {
+                    thinking: "The 49ers scored 26 and the Bengals 21.".to_string(),
+                    signature: "EqQBCgIYAhIM1gbcDa9GJwZA2b3h".to_string(),
+                },
+                ContentType::text("47 points were scored."),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_message_delta_with_thinking_tokens() -> Result<()> {
+        let event = parse_event(
+            r#"{"type":"message_delta","delta":{"stop_reason":"end_turn","stop_sequence":null},"usage":{"output_tokens":350,"thinking_tokens":312}}"#,
+        )?;
+
+        assert!(matches!(
+            event,
+            Some(StreamEvent::MessageDelta {
+                usage: DeltaUsage {
+                    output_tokens: 350,
+                    thinking_tokens: Some(312)
+                },
+                ..
+            })
+        ));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_collect_stream_closed_before_message_stop() -> Result<()> {
+        let truncated = &FIXTURE[..FIXTURE.find("event: message_delta").unwrap()];
+        let chunks: Vec<Result<&[u8]>> = vec![Ok(truncated.as_bytes())];
+
+        let response = collect(decode(futures::stream::iter(chunks), None)).await?;
+
+        assert!(response.incomplete);
+        assert_eq!(response.stop_reason, None);
+        assert_eq!(response.text(), "Okay, let's check the weather.");
+        assert_eq!(
+            response.tool_uses()[0].input["location"],
+            "San Francisco, CA"
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_collect_stream_cut_mid_block_is_an_error() {
+        let truncated = &FIXTURE[..FIXTURE.rfind("event: content_block_stop").unwrap()];
+        let chunks: Vec<Result<&[u8]>> = vec![Ok(truncated.as_bytes())];
+
+        let result = collect(decode(futures::stream::iter(chunks), None)).await;
+
+        assert!(matches!(result, Err(Error::Stream(_))));
+    }
+
+    #[tokio::test]
+    async fn test_write_ndjson() -> Result<()> {
+        let chunks: Vec<Result<&[u8]>> = vec![Ok(FIXTURE.as_bytes())];
+        let mut output = Vec::new();
+
+        write_ndjson(decode(futures::stream::iter(chunks), None), &mut output).await?;
+
+        let output = String::from_utf8(output).expect("Output is not UTF-8");
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<_, _>>()?;
+        assert!(output.ends_with('\n'));
+        assert_eq!(lines.first().unwrap()["type"], "message_start");
+        assert_eq!(lines.last().unwrap()["type"], "message_stop");
+
+        // Every line parses back into the event it was written from.
+        for line in &lines {
+            let event = parse_event(&line.to_string())?.expect("Unknown event");
+            assert_eq!(serde_json::to_value(&event)?, *line);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_tool_use_stop_reason_is_surfaced() -> Result<()> {
+        let chunks: Vec<Result<&[u8]>> = vec![Ok(FIXTURE.as_bytes())];
+
+        let events: Vec<StreamEvent> = decode(futures::stream::iter(chunks), None)
+            .try_collect()
+            .await?;
+
+        let stop_reasons: Vec<&StopReason> =
+            events.iter().filter_map(StreamEvent::stop_reason).collect();
+        assert_eq!(stop_reasons, vec![&StopReason::ToolUse]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+//! Reusing one request shape for many inputs.
+
+use crate::{BuildError, ClaudeRequest, ClaudeRequestBuilder};
+use std::fmt;
+use std::sync::Arc;
+
+/// A request with everything but the final user message filled in.
+///
+/// Created with [`ClaudeRequestBuilder::into_template`].
+#[derive(Clone)]
+pub struct RequestTemplate {
+    base: ClaudeRequestBuilder,
+    user: Arc<dyn Fn(&str) -> String + Send + Sync>,
+}
+
+impl fmt::Debug for RequestTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestTemplate")
+            .field("base", &self.base)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClaudeRequestBuilder {
+    /// Turns the builder into a template whose requests end with a user message made by `user`.
+    ///
+    /// The builder is checked as [`build`](Self::build) would, so rendering cannot fail.
+    pub fn into_template(
+        self,
+        user: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> Result<RequestTemplate, BuildError> {
+        // The text of the user message has no bearing on whether the request is valid.
+        self.clone().user(String::new()).build()?;
+
+        Ok(RequestTemplate {
+            base: self,
+            user: Arc::new(user),
+        })
+    }
+}
+
+impl RequestTemplate {
+    /// Creates the request for `input`.
+    pub fn render(&self, input: &str) -> ClaudeRequest {
+        self.base
+            .clone()
+            .user((self.user)(input))
+            .build()
+            .expect("Template was checked when it was created")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ContentType, Model, Role};
+
+    #[test]
+    fn test_render_two_requests() {
+        let template = ClaudeRequest::builder()
+            .model(Model::Sonnet35)
+            .system("You are an expert economic analyst specializing in forex markets.")
+            .max_tokens(500)
+            .into_template(|country| {
+                format!("Analyze the latest news about the {} economy.", country)
+            })
+            .expect("Failed to create template");
+
+        let japan = template.render("Japan");
+        let brazil = template.render("Brazil");
+
+        for (request, country) in [(&japan, "Japan"), (&brazil, "Brazil")] {
+            assert_eq!(request.messages.len(), 1);
+            assert_eq!(request.messages[0].role, Role::User);
+            assert!(matches!(
+                &request.messages[0].content[..],
+                [ContentType::Text { text, .. }]
+                    if *text == format!("Analyze the latest news about the {} economy.", country)
+            ));
+            assert_eq!(request.max_tokens, 500);
+        }
+    }
+
+    #[test]
+    fn test_incomplete_template_is_rejected() {
+        let result = ClaudeRequest::builder()
+            .model(Model::Sonnet35)
+            .into_template(str::to_string);
+
+        assert!(matches!(result, Err(BuildError::MissingMaxTokens)));
+    }
+}
@@ -0,0 +1,26 @@
+//! Helpers shared by the unit tests.
+
+/// Asserts that a request serializes to the expected JSON.
+///
+/// Both sides are compared as `serde_json::Value`, so object key order does not matter.
+macro_rules! assert_request_eq {
+    ($actual:expr, $expected:expr $(,)?) => {{
+        let actual = serde_json::to_value(&$actual).expect("Failed to serialize request");
+        let expected: serde_json::Value = $expected;
+        pretty_assertions::assert_eq!(actual, expected);
+    }};
+}
+
+/// Returns a Messages API response from Haiku 3 with the given content and stop reason.
+pub(crate) fn message_json(content: serde_json::Value, stop_reason: &str) -> serde_json::Value {
+    serde_json::json!({
+        "id": "msg_01",
+        "type": "message",
+        "role": "assistant",
+        "model": "claude-3-haiku-20240307",
+        "content": content,
+        "stop_reason": stop_reason,
+        "stop_sequence": null,
+        "usage": {"input_tokens": 10, "output_tokens": 2}
+    })
+}
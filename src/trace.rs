@@ -0,0 +1,102 @@
+//! Spans around API calls, enabled by the `tracing` feature.
+
+use crate::{ClaudeRequest, Error, Result};
+use std::future::Future;
+use tokio::time::Instant;
+use tracing::field::Empty;
+use tracing::Instrument;
+
+/// Runs `call` inside a `claude.call` span recording the outcome of a message request.
+///
+/// The span has the `model` and estimated `input_tokens` of the request, and once the
+/// response arrives its `status`, `request_id` and `latency_ms`. For streamed responses the
+/// latency is the time until the stream starts. The request itself is logged at trace level,
+/// without the data of images and documents.
+pub(crate) async fn instrument(
+    request: &ClaudeRequest,
+    input_tokens: usize,
+    call: impl Future<Output = Result<reqwest::Response>>,
+) -> Result<reqwest::Response> {
+    let span = tracing::info_span!(
+        "claude.call",
+        model = request.model.id(),
+        input_tokens,
+        status = Empty,
+        request_id = Empty,
+        latency_ms = Empty,
+    );
+
+    span.in_scope(|| {
+        tracing::trace!(request = %request.redacted_for_logging(), "Sending Claude API request");
+    });
+
+    let start = Instant::now();
+    let result = call.instrument(span.clone()).await;
+    span.record("latency_ms", start.elapsed().as_millis() as u64);
+
+    let _entered = span.enter();
+    match &result {
+        Ok(response) => {
+            span.record("status", response.status().as_u16());
+            if let Some(request_id) = response
+                .headers()
+                .get("request-id")
+                .and_then(|value| value.to_str().ok())
+            {
+                span.record("request_id", request_id);
+            }
+            tracing::debug!("Claude API call succeeded");
+        }
+        Err(error) => {
+            if let Error::Api { status, .. } = error {
+                span.record("status", status.as_u16());
+            }
+            tracing::warn!(%error, "Claude API call failed");
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::message_json;
+    use crate::{ClaudeRequest, Client, Model, Result};
+    use serde_json::json;
+    use tracing_test::traced_test;
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    #[tokio::test]
+    #[traced_test]
+    async fn test_call_emits_span() -> Result<()> {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("request-id", "req_01")
+                    .set_body_json(message_json(
+                        json!([{"type": "text", "text": "Hello!"}]),
+                        "end_turn",
+                    )),
+            )
+            .mount(&server)
+            .await;
+
+        let client = Client::with_api_key("test-key").with_base_url(server.uri());
+        let request = ClaudeRequest::builder()
+            .model(Model::Haiku3)
+            .user("Hello")
+            .max_tokens(10)
+            .build()
+            .expect("Failed to build request");
+        client.call(&request).await?;
+
+        assert!(logs_contain("claude.call"));
+        assert!(logs_contain("model=\"claude-3-haiku-20240307\""));
+        assert!(logs_contain("status=200"));
+        assert!(logs_contain("request_id=\"req_01\""));
+
+        Ok(())
+    }
+}
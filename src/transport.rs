@@ -0,0 +1,156 @@
+//! Replacing the HTTP layer, e.g. to test code that uses a [`Client`](crate::Client) offline.
+
+use crate::{Error, Result};
+use futures::future::BoxFuture;
+use reqwest::StatusCode;
+use std::fmt;
+use std::time::Duration;
+
+/// Sends the requests of a [`Client`](crate::Client) in place of the network.
+///
+/// A client given a transport with [`Client::with_transport`](crate::Client::with_transport)
+/// hands it each request once headers and query parameters are set, and treats the returned
+/// status and body as the API's response. Retries, error handling and streaming work as they
+/// do over HTTP.
+pub trait Transport: fmt::Debug + Send + Sync {
+    /// Sends `request` and returns the status and body of the response.
+    fn send(&self, request: reqwest::Request) -> BoxFuture<'_, Result<(StatusCode, String)>>;
+}
+
+/// Sends `request` through `transport`, failing with [`Error::Timeout`] after `timeout`.
+pub(crate) async fn send(
+    transport: &dyn Transport,
+    request: reqwest::Request,
+    timeout: Option<Duration>,
+) -> Result<reqwest::Response> {
+    let (status, body) = match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, transport.send(request))
+            .await
+            .map_err(|_| Error::Timeout)??,
+        None => transport.send(request).await?,
+    };
+
+    let response = http::Response::builder()
+        .status(status)
+        .body(body)
+        .expect("Status and body form a valid response");
+    Ok(response.into())
+}
+
+#[cfg(feature = "testing")]
+pub use mock::MockTransport;
+
+#[cfg(feature = "testing")]
+mod mock {
+    use super::*;
+    use serde_json::Value;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    /// A [`Transport`] that answers with queued responses and records the requests it gets.
+    ///
+    /// Responses are returned in the order they were queued. A request arriving when none
+    /// are left panics, failing the test that sent it.
+    #[derive(Debug, Default)]
+    pub struct MockTransport {
+        responses: Mutex<VecDeque<(StatusCode, String)>>,
+        requests: Mutex<Vec<Value>>,
+    }
+
+    impl MockTransport {
+        /// Creates a MockTransport with no responses queued.
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Queues a response with the given status and body.
+        pub fn push_response(&self, status: StatusCode, body: impl Into<String>) -> &Self {
+            self.responses
+                .lock()
+                .expect("MockTransport lock poisoned")
+                .push_back((status, body.into()));
+            self
+        }
+
+        /// Queues a successful response with `body` as JSON, e.g. a message.
+        pub fn push_json(&self, body: Value) -> &Self {
+            self.push_response(StatusCode::OK, body.to_string())
+        }
+
+        /// Returns the JSON bodies of the requests received so far, `null` for those without.
+        pub fn requests(&self) -> Vec<Value> {
+            self.requests
+                .lock()
+                .expect("MockTransport lock poisoned")
+                .clone()
+        }
+    }
+
+    impl Transport for MockTransport {
+        fn send(&self, request: reqwest::Request) -> BoxFuture<'_, Result<(StatusCode, String)>> {
+            let body = request
+                .body()
+                .and_then(|body| body.as_bytes())
+                .and_then(|bytes| serde_json::from_slice(bytes).ok())
+                .unwrap_or(Value::Null);
+            self.requests
+                .lock()
+                .expect("MockTransport lock poisoned")
+                .push(body);
+
+            let response = self
+                .responses
+                .lock()
+                .expect("MockTransport lock poisoned")
+                .pop_front()
+                .unwrap_or_else(|| panic!("MockTransport has no response for {}", request.url()));
+            Box::pin(async move { Ok(response) })
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_util::message_json;
+        use crate::{ClaudeRequest, Client, Model, RetryPolicy};
+        use serde_json::json;
+        use std::sync::Arc;
+
+        fn message(text: &str) -> Value {
+            message_json(json!([{"type": "text", "text": text}]), "end_turn")
+        }
+
+        #[tokio::test]
+        async fn test_call_with_mock_transport() -> Result<()> {
+            let transport = Arc::new(MockTransport::new());
+            transport
+                .push_response(
+                    StatusCode::from_u16(529).unwrap(),
+                    r#"{"type":"error","error":{"type":"overloaded_error","message":"Overloaded"}}"#,
+                )
+                .push_json(message("Hello!"));
+
+            let client = Client::with_api_key("test-key")
+                .with_retry(RetryPolicy {
+                    base_delay: Duration::from_millis(1),
+                    ..RetryPolicy::default()
+                })
+                .with_transport(transport.clone());
+            let request = ClaudeRequest::builder()
+                .model(Model::Haiku3)
+                .user("Hello")
+                .max_tokens(10)
+                .build()
+                .expect("Failed to build request");
+
+            let response = client.call(&request).await?;
+
+            assert_eq!(response.text(), "Hello!");
+            let requests = transport.requests();
+            assert_eq!(requests.len(), 2);
+            assert_eq!(requests[1]["messages"][0]["content"][0]["text"], "Hello");
+
+            Ok(())
+        }
+    }
+}
@@ -9,6 +9,7 @@ async fn test_qa() {
             Role::User,
             vec![ContentType::Text {
                 text: "who was the 16th president of the USA?".to_string(),
+                cache_control: None,
             }],
         )
         .max_tokens(200)
@@ -12,6 +12,7 @@ async fn test_simple_api_request() {
             Role::User,
             vec![ContentType::Text {
                 text: "Say hello!".to_string(),
+                cache_control: None,
             }],
         )
         .max_tokens(10)
@@ -32,6 +33,7 @@ async fn test_tool_use_request_body_valid() -> Result<()> {
             Role::Assistant,
             vec![ContentType::Text {
                 text: "You're an NFL expert extract the game info.".to_string(),
+                cache_control: None,
             }],
         )
         .add_message(
@@ -39,9 +41,10 @@ async fn test_tool_use_request_body_valid() -> Result<()> {
             vec![ContentType::Text {
                 text: "The Green Bay Packers beat the Miami Dolphins in the 1982 Super Bowl 31-10."
                     .to_string(),
+                cache_control: None,
             }],
         )
-        .build();
+        .build()?;
 
     let expected = r#"{
          "model": "claude-3-opus-20240229",
@@ -103,7 +106,8 @@ async fn test_tool_use_request_body_valid() -> Result<()> {
                    "winner",
                    "winner_score",
                    "year"
-                 ]
+                 ],
+                 "additionalProperties": false
                }
              }
           ],